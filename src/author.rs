@@ -0,0 +1,144 @@
+//! Authors of the stories, from the `credits.by` of ANS.
+//!
+//! Stored in the `authors` partition under `<site id>\0<slug>`, and their stories in
+//! `bylines` under `<site id>\0<slug>\0<display_date><website_url>`, so a reverse prefix
+//! scan lists them newest first. Slugs are only unique within a site, and the name
+//! fallback would merge namesakes, so the authors of each site are kept apart.
+
+use fjall::PartitionHandle;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Author {
+    pub slug: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    /// url of the original photo, downloaded with the story images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photo: Option<String>,
+    /// profile page on the origin site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// id of the site the author wrote for
+    pub site: String,
+}
+
+impl Author {
+    /// The author of a `credits.by` entry, `None` for credits without a name
+    pub fn from_credit(credit: &Value, site: &str) -> Option<Author> {
+        let original = &credit["additional_properties"]["original"];
+        let name = [&credit["name"], &original["byline"]]
+            .into_iter()
+            .find_map(|n| n.as_str().map(str::trim).filter(|n| !n.is_empty()))?;
+        let url = non_empty(&credit["url"]);
+        let slug = non_empty(&credit["slug"])
+            .and_then(|s| slugify(&s))
+            .or_else(|| url.as_deref().and_then(url_slug))
+            .or_else(|| slugify(name))?;
+        Some(Author {
+            slug,
+            name: name.to_owned(),
+            bio: [
+                &original["longBio"],
+                &original["bio"],
+                &credit["description"],
+            ]
+            .into_iter()
+            .find_map(non_empty),
+            photo: non_empty(&credit["image"]["url"]),
+            url,
+            site: site.to_owned(),
+        })
+    }
+
+    /// Authors credited by a story, without duplicates
+    pub fn of_story(story: &Value, site: &str) -> Vec<Author> {
+        let mut authors: Vec<Author> = vec![];
+        for credit in story["credits"]["by"].as_array().into_iter().flatten() {
+            if let Some(author) = Author::from_credit(credit, site)
+                && !authors.iter().any(|a| a.slug == author.slug)
+            {
+                authors.push(author);
+            }
+        }
+        authors
+    }
+
+    pub fn load(authors: &PartitionHandle, site: &str, slug: &str) -> Option<Author> {
+        let v = authors.get(author_key(site, slug)).unwrap()?;
+        serde_json::from_slice(&v).ok()
+    }
+
+    /// Update with a newer credit, keeping what it lacks
+    pub fn merge(&mut self, newer: Author) {
+        self.name = newer.name;
+        self.bio = newer.bio.or(self.bio.take());
+        self.photo = newer.photo.or(self.photo.take());
+        self.url = newer.url.or(self.url.take());
+    }
+}
+
+fn non_empty(v: &Value) -> Option<String> {
+    v.as_str()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+}
+
+/// Last segment of a profile url, e.g. `/author/jane-doe/`
+fn url_slug(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    slugify(path.trim_end_matches('/').rsplit('/').next()?)
+}
+
+/// Lowercase letters and digits of any script, other runs become `-`
+pub fn slugify(s: &str) -> Option<String> {
+    let mut slug = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    (!slug.is_empty()).then(|| slug.to_owned())
+}
+
+pub fn author_key(site: &str, slug: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(site.len() + slug.len() + 1);
+    key.extend_from_slice(site.as_bytes());
+    key.push(0);
+    key.extend_from_slice(slug.as_bytes());
+    key
+}
+
+pub fn byline_key(
+    site: &str,
+    slug: &str,
+    website_url: &str,
+    display_date: &str,
+) -> Option<Vec<u8>> {
+    let ts: Timestamp = display_date.parse().ok()?;
+    let mut key = byline_prefix(site, slug);
+    key.extend_from_slice(&ts.as_second().to_be_bytes());
+    key.extend_from_slice(website_url.trim_matches('/').as_bytes());
+    Some(key)
+}
+
+pub fn byline_prefix(site: &str, slug: &str) -> Vec<u8> {
+    let mut key = author_key(site, slug);
+    key.push(0);
+    key
+}
+
+/// `website_url` of a `bylines` key
+pub fn byline_url(key: &[u8]) -> Option<String> {
+    // site ids and slugs hold no NUL, the timestamp may
+    let slug = key.iter().position(|b| *b == 0)? + 1;
+    let start = slug + key[slug..].iter().position(|b| *b == 0)? + 9;
+    Some(String::from_utf8_lossy(key.get(start..)?).into_owned())
+}
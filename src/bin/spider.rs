@@ -1,295 +1,2240 @@
-use clap::Parser;
-use fjall::{Config, Keyspace, PartitionCreateOptions, PartitionHandle};
+use clap::{CommandFactory, Parser, Subcommand, error::ErrorKind};
+use fjall::{
+    Batch, Config, GarbageCollection, Keyspace, PartitionCreateOptions, PartitionHandle,
+    PersistMode,
+};
+use futures::{StreamExt, future, stream};
 use jiff::{
-    ToSpan, Zoned,
+    Span, SpanRelativeTo, Timestamp, ToSpan, Zoned,
     civil::{Date, date},
+    tz::TimeZone,
+};
+use reqwest::{
+    StatusCode,
+    header::{CONTENT_TYPE, HeaderMap},
+};
+use rfa::{
+    author::{Author, author_key, byline_key},
+    blob_path,
+    compress::Codec,
+    daemon::{DaemonStatus, TaskStatus},
+    discover::{self, Links},
+    get_filename_from_url, index_key, kv_sep_partition_option,
+    proxy::ProxyPool,
+    replay,
+    report::{ObjStats, RunReport, WindowReport},
+    revision_key, revision_ts,
+    section::{Section, section_key, section_prefix},
+    site::{Site, Sites},
+    source::{self, Backend, Image, Listing, Objects, Query, Source, StoryFilter, StoryRef},
+    throttle::Throttle,
+    warc::WarcWriter,
 };
-use reqwest::Proxy;
-use rfa::{get_filename_from_url, index_key, kv_sep_partition_option};
 use serde_json::{Value, json};
 use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     error::Error,
-    fs::create_dir_all,
+    fs::{File, create_dir_all},
+    io::{self, Cursor, Write},
     path::{Path, PathBuf},
-    sync::LazyLock,
+    sync::{
+        LazyLock, Mutex, Once,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{Notify, RwLock, Semaphore},
 };
-use tracing::{error, info, instrument};
-use urlencoding::encode;
+use tracing::{error, info, instrument, warn};
 
-static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
-    let mut client_builder = reqwest::Client::builder();
-    if let Some(proxy) = &ARGS.proxy {
-        client_builder = client_builder.proxy(Proxy::all(proxy).unwrap());
+/// Direct client, for replays and when no proxy is given
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| client_builder().build().unwrap());
+
+/// Proxies of `--proxy` and `--proxy-file`, unused when replaying
+static PROXIES: LazyLock<Option<ProxyPool>> = LazyLock::new(|| {
+    let mut urls = ARGS.proxy.clone();
+    if let Some(path) = &ARGS.proxy_file {
+        let content = std::fs::read_to_string(path).unwrap();
+        urls.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(str::to_owned),
+        );
+    }
+    if urls.is_empty() || ARGS.replay.is_some() {
+        return None;
     }
-    let retry = reqwest::retry::for_host("www.rfa.org").max_retries_per_request(10);
-    client_builder
+    Some(ProxyPool::new(&urls, client_builder).unwrap())
+});
+
+fn client_builder() -> reqwest::ClientBuilder {
+    let hosts = REGISTRY.iter().map(|s| s.source.source().host()).collect();
+    let retry = reqwest::retry::for_host(Hosts(hosts)).max_retries_per_request(10);
+    reqwest::Client::builder()
         .retry(retry)
         .danger_accept_invalid_certs(true)
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .unwrap()
-});
+        // no total timeout, media downloads under a bandwidth cap can take long
+        .connect_timeout(Duration::from_secs(30))
+        .read_timeout(Duration::from_secs(30))
+}
+
+/// Hosts of the sources, matched against the host of a request
+struct Hosts(Vec<String>);
+
+impl PartialEq<&str> for Hosts {
+    fn eq(&self, host: &&str) -> bool {
+        self.0.iter().any(|h| h == host)
+    }
+}
 
 const SIZE: u64 = 100;
 
-const SITE_LIST: [&str; 10] = [
-    "radio-free-asia", // English
-    "rfa-mandarin",
-    "rfa-cantonese",
-    "rfa-burmese",
-    "rfa-korean",
-    "rfa-lao",
-    "rfa-khmer",
-    "rfa-tibetan",
-    "rfa-uyghur",
-    "rfa-vietnamese",
-];
+/// bytes kept in memory to check the image header
+const HEAD_SIZE: usize = 256 * 1024;
+
+/// failed downloads retried by later runs, those failing more are left to `verify --repair`
+const MAX_ATTEMPTS: u64 = 5;
 
 /// RFA website crawler, downloading lists, pages and imgs
 #[derive(Parser, Debug)]
 struct Args {
-    /// Website to fetch (e.g., rfa-mandarin, rfa-korean)
-    #[arg(short = 'w', long, value_delimiter = ',', help = SITE_LIST.join(","))]
+    /// websites to fetch by Arc id (e.g., rfa-mandarin,rfa-korean), all registered sites by default
+    #[arg(short = 'w', long, value_delimiter = ',', global = true)]
     sites: Vec<String>,
 
-    /// proxy (e.g., http://127.0.0.1:8089)
-    #[clap(long)]
-    proxy: Option<String>,
+    /// site registry, a JSON list of sites [default: <OUTPUT>/sites.json if present, else the RFA services]
+    #[arg(long, global = true)]
+    sites_config: Option<PathBuf>,
+
+    /// store the complete ANS documents of Arc stories, instead of the fields the web uses
+    #[arg(long, global = true, conflicts_with = "filter_file")]
+    full_stories: bool,
+
+    /// fields of the Arc stories to store, read from a file, keeping at least `_id,display_date,websites{$site{website_url}}`
+    #[arg(long, global = true)]
+    filter_file: Option<PathBuf>,
+
+    /// proxies, rotated per request (e.g., http://127.0.0.1:8089,socks5h://127.0.0.1:9050)
+    #[arg(long, value_delimiter = ',', global = true)]
+    proxy: Vec<String>,
+
+    /// read more proxies from a file, one per line
+    #[arg(long, global = true)]
+    proxy_file: Option<PathBuf>,
+
+    /// url requested through each proxy before use [default: robots.txt of the first site]
+    #[arg(long, global = true)]
+    proxy_check_url: Option<String>,
 
-    #[arg(short = 'o', long, default_value = "rfa_data")]
+    #[arg(short = 'o', long, default_value = "rfa_data", global = true)]
     output: String,
+
+    /// only re-crawl the last N days (including today) and upsert new or changed stories, instead of `--from`/`--to`
+    #[arg(long)]
+    refresh_days: Option<u32>,
+
+    /// first month to crawl (e.g., 2008-03) [default: 1998-01]
+    #[arg(long, value_parser = parse_month)]
+    from: Option<Date>,
+
+    /// last month to crawl (e.g., 2008-05) [default: previous month]
+    #[arg(long, value_parser = parse_month)]
+    to: Option<Date>,
+
+    /// crawl the newest months first, across all sites
+    #[arg(long)]
+    newest_first: bool,
+
+    /// re-crawl months already marked done, e.g. to update a period with `--from`/`--to`
+    #[arg(long)]
+    recrawl: bool,
+
+    /// number of months fetched concurrently
+    #[arg(short = 'j', long, default_value_t = 2)]
+    jobs: usize,
+
+    /// number of images and media files downloaded concurrently, shared by all months
+    #[arg(long, default_value_t = 8)]
+    img_jobs: usize,
+
+    /// max requests per second, shared by all tasks
+    #[arg(long)]
+    rps: Option<f64>,
+
+    /// max random delay added before each request, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u64,
+
+    /// max requests per UTC day, once used up the crawl waits for the next day
+    #[arg(long)]
+    daily_budget: Option<u64>,
+
+    /// max download bandwidth of images and media, in bytes per second
+    #[arg(long)]
+    max_bytes_per_sec: Option<u64>,
+
+    /// save every response as a fixture file into this folder, for `replay-server`
+    #[arg(long, global = true)]
+    record: Option<PathBuf>,
+
+    /// send all requests to the `replay-server` at this address (e.g., 127.0.0.1:3334)
+    #[arg(long, global = true)]
+    replay: Option<String>,
+
+    /// also write the HTTP exchanges as WARC files with a CDX index into this folder
+    #[arg(long, global = true)]
+    warc: Option<PathBuf>,
+
+    /// size after which a new WARC file is started, in MB
+    #[arg(long, default_value_t = 1000, global = true)]
+    warc_max_mb: u64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the reports of crawl runs as JSON
+    Report {
+        /// id of the run, defaults to the latest ones
+        run: Option<i64>,
+
+        /// number of latest runs to print
+        #[arg(long, default_value_t = 1)]
+        last: usize,
+
+        /// write to a file instead of stdout
+        #[arg(long)]
+        export: Option<PathBuf>,
+    },
+
+    /// Fetch single stories now, by url or ANS `_id`
+    Fetch {
+        /// story urls (e.g., https://www.rfa.org/mandarin/…) or `_id`s, ids need `--sites`
+        stories: Vec<String>,
+
+        /// read more urls or ids from a file, one per line
+        #[arg(long)]
+        from_file: Option<PathBuf>,
+
+        /// also fetch the stories queued by `discover`
+        #[arg(long)]
+        queue: bool,
+    },
+
+    /// Find stories missing from the archive in the sitemaps and feeds of the sites,
+    /// queue them for `fetch --queue` and print the gaps as JSON
+    Discover {
+        /// fetch the queued stories right away
+        #[arg(long)]
+        fetch: bool,
+
+        /// max sitemaps and feeds fetched per site
+        #[arg(long, default_value_t = 500)]
+        max_documents: usize,
+    },
+
+    /// Check the archive is consistent and print a JSON report
+    Verify {
+        /// re-download missing objects, re-derive missing index entries, drop
+        /// dangling ones and clear `done` markers of empty months
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// Train a zstd dictionary per site on its stories, then recompress them and
+    /// their revisions with it. Also converts archives stored as plain JSON.
+    Compress {
+        /// max stories per site to train the dictionary on
+        #[arg(long, default_value_t = 5000)]
+        samples: usize,
+    },
+
+    /// Run forever: refresh the last `--refresh-days` (3 by default) often, and
+    /// crawl the months not done yet then `verify --repair` seldom
+    Daemon {
+        /// between two refreshes (e.g., 30m, 6h)
+        #[arg(long, default_value = "1h", value_parser = parse_interval)]
+        refresh_every: Duration,
+
+        /// between two backfills
+        #[arg(long, default_value = "24h", value_parser = parse_interval)]
+        backfill_every: Duration,
+
+        /// JSON file the state of the daemon is written to, in the output folder
+        #[arg(long, default_value = "daemon.json")]
+        status: PathBuf,
+    },
+
+    /// Serve the fixtures saved with `--record`, as a stand-in of the origins for `--replay`
+    ReplayServer {
+        /// folder of the fixtures
+        fixtures: PathBuf,
+
+        /// listening address
+        #[arg(short, long, default_value = "127.0.0.1:3334")]
+        addr: String,
+    },
 }
 
 static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
+
+/// `2008-03` or `2008-03-14`, as the first day of the month
+fn parse_month(s: &str) -> Result<Date, String> {
+    let s = if s.len() == 7 {
+        format!("{s}-01")
+    } else {
+        s.to_owned()
+    };
+    let d: Date = s.parse().map_err(|e| format!("{e}"))?;
+    Ok(d.first_of_month())
+}
+
+/// First and last month of a crawl, `--from` and `--to` or their defaults
+fn crawl_months() -> (Date, Date) {
+    let from = ARGS.from.unwrap_or(date(1998, 1, 1));
+    let to = ARGS
+        .to
+        .unwrap_or_else(|| Zoned::now().date().saturating_sub(1.month()))
+        .first_of_month();
+    (from, to)
+}
+
+/// Exit with a usage error on options clap can't tell apart, as the daemon takes
+/// both `--refresh-days` and `--from`/`--to`
+fn check_args() {
+    let crawling = matches!(ARGS.command, None | Some(Command::Daemon { .. }));
+    if !crawling {
+        return;
+    }
+    let mut cmd = Args::command();
+    if ARGS.command.is_none()
+        && ARGS.refresh_days.is_some()
+        && (ARGS.from.is_some() || ARGS.to.is_some())
+    {
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--refresh-days can't be used with --from or --to, except by `daemon`",
+        )
+        .exit();
+    }
+    let (from, to) = crawl_months();
+    if from > to {
+        cmd.error(
+            ErrorKind::ValueValidation,
+            format!(
+                "--from {} is after --to {}",
+                from.strftime("%Y-%m"),
+                to.strftime("%Y-%m")
+            ),
+        )
+        .exit();
+    }
+}
+
+/// `90s`, `30m`, `6h`, `1d`…
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let span: Span = s.parse().map_err(|e| format!("{e}"))?;
+    let duration = span
+        .to_duration(SpanRelativeTo::days_are_24_hours())
+        .map_err(|e| format!("{e}"))?;
+    Duration::try_from(duration)
+        .ok()
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("{s} is not a positive interval"))
+}
+static THROTTLE: LazyLock<Throttle> = LazyLock::new(|| {
+    Throttle::new(
+        ARGS.rps,
+        Duration::from_millis(ARGS.jitter_ms),
+        ARGS.daily_budget,
+        ARGS.max_bytes_per_sec,
+    )
+});
+static DL_PERMITS: LazyLock<Semaphore> = LazyLock::new(|| Semaphore::new(ARGS.img_jobs.max(1)));
+static REGISTRY: LazyLock<Sites> = LazyLock::new(|| {
+    let path = match &ARGS.sites_config {
+        Some(path) => path.clone(),
+        None => Path::new(&ARGS.output).join("sites.json"),
+    };
+    let mut sites = Sites::load(&path).unwrap_or_else(|e| {
+        Args::command()
+            .error(
+                ErrorKind::Io,
+                format!("site registry {}: {e}", path.display()),
+            )
+            .exit()
+    });
+    let filter = match &ARGS.filter_file {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(fields) => Some(StoryFilter::Custom(fields)),
+            Err(e) => Args::command()
+                .error(
+                    ErrorKind::Io,
+                    format!("filter file {}: {e}", path.display()),
+                )
+                .exit(),
+        },
+        None => ARGS.full_stories.then_some(StoryFilter::Full),
+    };
+    if let Some(filter) = filter {
+        for site in sites.iter_mut() {
+            if let Backend::Arc(arc) = &mut site.source {
+                arc.filter = filter.clone();
+            }
+        }
+    }
+    // stories without these fields could not be stored
+    for site in sites.iter() {
+        if let Backend::Arc(arc) = &site.source
+            && let Err(e) = arc.filter.check()
+        {
+            Args::command()
+                .error(ErrorKind::ValueValidation, format!("{}: {e}", site.id))
+                .exit();
+        }
+    }
+    sites
+});
+static RECORD_DIR: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
+    let dir = std::path::absolute(ARGS.record.as_ref()?).unwrap();
+    create_dir_all(&dir).unwrap();
+    Some(dir)
+});
+static WARC: LazyLock<Option<WarcWriter>> = LazyLock::new(|| {
+    let dir = std::path::absolute(ARGS.warc.as_ref()?).unwrap();
+    Some(WarcWriter::new(&dir, ARGS.warc_max_mb * 1_000_000).unwrap())
+});
 static SITES: LazyLock<Vec<String>> = LazyLock::new(|| {
     if ARGS.sites.is_empty() {
         info!("No website specified, fetching all available websites.");
-        SITE_LIST.iter().map(|s| s.to_string()).collect()
+        REGISTRY.iter().map(|s| s.id.clone()).collect()
     } else {
+        // ids as the registry spells them, the rest of the spider looks them up
+        let mut sites = vec![];
         for site in &ARGS.sites {
             let site = site.trim().to_lowercase();
-            if !SITE_LIST.contains(&site.as_str()) {
-                panic!(
-                    "Unknown website: {}, available options are: {:?}",
-                    site, SITE_LIST
-                );
+            if site.is_empty() || sites.contains(&site) {
+                continue;
+            }
+            if REGISTRY.by_id(&site).is_none() {
+                Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!(
+                            "unknown website {site}, available options are: {:?}",
+                            REGISTRY.iter().map(|s| &s.id).collect::<Vec<_>>()
+                        ),
+                    )
+                    .exit();
             }
+            sites.push(site);
         }
-        ARGS.sites.clone()
+        sites
     }
 });
+/// Keeps the windows of a site apart when the daemon refreshes during a backfill.
+/// The months of a crawl are disjoint and share the lock, a refresh overlaps the
+/// recent ones and takes it alone, and `verify` shares it with crawls too.
+static SITE_LOCKS: LazyLock<HashMap<String, RwLock<()>>> = LazyLock::new(|| {
+    REGISTRY
+        .iter()
+        .map(|site| (site.id.clone(), RwLock::new(())))
+        .collect()
+});
+
+fn site_lock(site: &str) -> Result<&'static RwLock<()>, String> {
+    SITE_LOCKS
+        .get(site)
+        .ok_or_else(|| format!("unknown website {site}"))
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
+        .with_writer(std::io::stderr)
         .init();
 
+    check_args();
+
+    // relative to the working dir, so load before leaving it
+    LazyLock::force(&REGISTRY);
+    LazyLock::force(&RECORD_DIR);
+    LazyLock::force(&PROXIES);
+    LazyLock::force(&WARC);
+
+    if let Some(Command::ReplayServer { fixtures, addr }) = &ARGS.command {
+        let listener = TcpListener::bind(addr).await?;
+        replay::serve(fixtures.clone(), listener).await?;
+        return Ok(());
+    }
+
+    // unknown websites are a usage error, before any work
+    LazyLock::force(&SITES);
+
     let path = Path::new(&ARGS.output);
     if !path.exists() {
         create_dir_all(path)?;
     }
     std::env::set_current_dir(path)?;
 
-    let img_path = PathBuf::from("imgs");
-    if !img_path.exists() {
-        create_dir_all(img_path)?;
+    let db = Db::open()?;
+
+    if let Some(pool) = &*PROXIES
+        && !matches!(ARGS.command, Some(Command::Report { .. }))
+    {
+        let url = match &ARGS.proxy_check_url {
+            Some(url) => url.clone(),
+            None => format!(
+                "https://{}/robots.txt",
+                REGISTRY.first().source.source().host()
+            ),
+        };
+        info!("Checking {} proxies with {url}", pool.len());
+        pool.check(&url).await;
+    }
+
+    let res = match &ARGS.command {
+        Some(Command::Report { run, last, export }) => report(&db, *run, *last, export.as_deref()),
+        Some(Command::Verify { repair }) => verify(&db, *repair).await.and_then(|report| {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(())
+        }),
+        Some(Command::Fetch {
+            stories,
+            from_file,
+            queue,
+        }) => {
+            let mut stories = stories.clone();
+            if let Some(path) = from_file {
+                let content = std::fs::read_to_string(path)?;
+                stories.extend(
+                    content
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                        .map(str::to_owned),
+                );
+            }
+            if *queue {
+                stories.extend(queued(&db)?);
+            }
+            fetch_stories(&db, &stories).await
+        }
+        Some(Command::Discover {
+            fetch,
+            max_documents,
+        }) => {
+            discover(&db, *max_documents).await?;
+            if *fetch {
+                fetch_stories(&db, &queued(&db)?).await?;
+            }
+            Ok(())
+        }
+        Some(Command::Compress { samples }) => compress(&db, *samples),
+        Some(Command::Daemon {
+            refresh_every,
+            backfill_every,
+            status,
+        }) => daemon(&db, *refresh_every, *backfill_every, status).await,
+        Some(Command::ReplayServer { .. }) => unreachable!(),
+        None => crawl(&db, ARGS.refresh_days).await.map(|_| ()),
+    };
+    if let Some(warc) = &*WARC {
+        warc.finish()?;
     }
+    res
+}
 
-    let keyspace = Config::new("rfa.db").open().unwrap();
-    let db = keyspace
-        .open_partition("rfa", kv_sep_partition_option())
-        .unwrap();
+async fn crawl(db: &Db, refresh_days: Option<u32>) -> Result<RunReport, Box<dyn Error>> {
+    for dir in [Kind::Img.dir(), Kind::Media.dir()] {
+        create_dir_all(dir)?;
+    }
 
-    let done = keyspace
-        .open_partition("done", PartitionCreateOptions::default())
-        .unwrap();
+    listen_shutdown();
 
-    let index = keyspace
-        .open_partition("index", PartitionCreateOptions::default())
-        .unwrap();
+    let (today, _) = THROTTLE.used();
+    if let Some(v) = db.budget.get(today.to_string())? {
+        THROTTLE.set_used(today, u64::from_be_bytes(v[..].try_into()?));
+    }
 
+    retry_failed(db).await;
     for site in &*SITES {
-        info!("Processing website: {}", site);
-        let mut start_date = date(1998, 1, 1);
-        let end_date = Zoned::now()
-            .date()
-            .saturating_sub(1.month())
-            .last_of_month();
-        while start_date <= end_date {
-            fetch_articles(
-                &keyspace,
-                &db,
-                &done,
-                &index,
-                site,
-                start_date.year(),
-                start_date.month(),
-            )
-            .await?;
-            start_date = start_date.saturating_add(1.month());
+        if let Some(site) = REGISTRY.by_id(site) {
+            fetch_sections(db, site).await;
+        }
+    }
+
+    let mode = if refresh_days.is_some() {
+        "refresh"
+    } else {
+        "crawl"
+    };
+    let mut run = RunReport::new(mode, &SITES);
+    run.save(&db.runs);
+
+    if let Some(days) = refresh_days {
+        let end = Zoned::now().date().tomorrow()?;
+        let begin = end.saturating_sub((days as i64 + 1).days());
+        for site in &*SITES {
+            info!("Refreshing website: {} ({} to {})", site, begin, end);
+            run.windows
+                .push(refresh(db, &run, site, &begin, &end).await);
+        }
+    } else {
+        let (from, to) = crawl_months();
+        // a feed lists the same latest stories for every month, fetch it once
+        let (latest, sites): (Vec<&String>, Vec<&String>) = SITES.iter().partition(|site| {
+            REGISTRY
+                .by_id(site)
+                .is_some_and(|s| s.source.source().latest_only())
+        });
+        for site in latest {
+            info!("Fetching the latest stories of {site}");
+            let report = refresh(db, &run, site, &from, &to.last_of_month()).await;
+            run.windows.push(report);
         }
+
+        let mut month_list: Vec<Date> = from.series(1.month()).take_while(|d| *d <= to).collect();
+        let months: Vec<(&String, Date)> = if ARGS.newest_first {
+            month_list.reverse();
+            month_list
+                .iter()
+                .flat_map(|d| sites.iter().map(move |site| (*site, *d)))
+                .collect()
+        } else {
+            sites
+                .iter()
+                .flat_map(|site| month_list.iter().map(move |d| (*site, *d)))
+                .collect()
+        };
+        info!(
+            "Crawling {} months from {} to {}",
+            month_list.len(),
+            from,
+            to
+        );
+
+        // every month commits its own batch before writing its `done` marker,
+        // so running them concurrently keeps the partitions consistent.
+        let windows: Vec<WindowReport> = stream::iter(months)
+            .take_while(|_| future::ready(!shutting_down()))
+            .map(|(site, d)| fetch_articles(db, &run, site, d.year(), d.month()))
+            .buffer_unordered(ARGS.jobs.max(1))
+            .filter_map(future::ready)
+            .collect()
+            .await;
+        run.windows.extend(windows);
+    }
+
+    if let Some(pool) = &*PROXIES {
+        run.proxies = pool.stats();
+        for p in &run.proxies {
+            info!(
+                "Proxy {}: {} requests, {} failures, {} rate-limited{}",
+                p.proxy,
+                p.requests,
+                p.failures,
+                p.rate_limited,
+                if p.healthy { "" } else { ", on cooldown" }
+            );
+        }
+    }
+    run.finish(&db.runs);
+    let totals = run.totals();
+    info!(
+        "Run {} finished: {} windows, {} stories fetched, {} stored, {} images and {} media downloaded, {} downloads failed, {} errors",
+        run.id,
+        run.windows.len(),
+        totals.fetched,
+        totals.stored,
+        totals.imgs.downloaded,
+        totals.media.downloaded,
+        totals.imgs.failed + totals.media.failed,
+        totals.errors.len()
+    );
+
+    db.save_budget();
+    db.keyspace.persist(PersistMode::SyncAll)?;
+    if shutting_down() {
+        info!("Stopped, progress saved.");
+    }
+
+    Ok(run)
+}
+
+/// Run the refresh and the backfill forever, each on its interval, until a signal.
+/// Their state goes to the `status` file.
+async fn daemon(
+    db: &Db,
+    refresh_every: Duration,
+    backfill_every: Duration,
+    status_path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    listen_shutdown();
+    let mut status = DaemonStatus {
+        pid: std::process::id(),
+        started: Timestamp::now().to_string(),
+        refresh: TaskStatus::new(refresh_every),
+        backfill: TaskStatus::new(backfill_every),
+        ..Default::default()
+    };
+    status.save(status_path)?;
+    info!(
+        "Daemon started, refreshing every {}s and backfilling every {}s",
+        refresh_every.as_secs(),
+        backfill_every.as_secs()
+    );
+
+    // a long backfill must not hold the refresh back, see `SITE_LOCKS`
+    let status = Mutex::new(status);
+    let (refresh, backfill) = tokio::join!(
+        daemon_loop(db, false, &status, status_path),
+        daemon_loop(db, true, &status, status_path),
+    );
+    refresh?;
+    backfill?;
+    info!("Daemon stopped.");
+    Ok(())
+}
+
+/// Run the refresh or the backfill on its own timer until shutdown
+async fn daemon_loop(
+    db: &Db,
+    backfill: bool,
+    status: &Mutex<DaemonStatus>,
+    status_path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let name = if backfill { "backfill" } else { "refresh" };
+    let mut next = tokio::time::Instant::now();
+    loop {
+        let stop = STOP.notified();
+        if shutting_down() {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(next) => {}
+            _ = stop => break,
+        }
+
+        info!("Starting the {name}");
+        {
+            let mut status = status.lock().unwrap();
+            status.task(backfill).start();
+            status.save(status_path)?;
+        }
+
+        let (run, summary, error) = daemon_task(db, backfill).await;
+        if let Some(warc) = &*WARC {
+            warc.finish()?;
+        }
+        if let Some(e) = &error {
+            error!("The {name} failed: {e}");
+        }
+        let delay = {
+            let mut status = status.lock().unwrap();
+            let task = status.task(backfill);
+            task.last_run = run.or(task.last_run);
+            task.summary = summary;
+            let delay = task.finish(error);
+            status.save(status_path)?;
+            delay
+        };
+        info!("Next {name} in {}s", delay.as_secs());
+        next = tokio::time::Instant::now() + delay;
+    }
+    Ok(())
+}
+
+/// One run of a daemon task: its crawl run id, a summary and why it failed, if it did
+async fn daemon_task(db: &Db, backfill: bool) -> (Option<i64>, Value, Option<String>) {
+    let refresh_days = (!backfill).then(|| ARGS.refresh_days.unwrap_or(3));
+    let run = match crawl(db, refresh_days).await {
+        Ok(run) => run,
+        Err(e) => return (None, Value::Null, Some(e.to_string())),
+    };
+    let mut summary = json!({ "totals": run.totals() });
+
+    // an outage fails every window
+    let failed: Vec<&WindowReport> = run
+        .windows
+        .iter()
+        .filter(|w| !w.errors.is_empty())
+        .collect();
+    let mut error = (!failed.is_empty() && failed.len() == run.windows.len()).then(|| {
+        format!(
+            "all {} windows failed: {}",
+            failed.len(),
+            failed[0].errors[0]
+        )
+    });
+
+    if backfill && error.is_none() && !shutting_down() {
+        match verify(db, true).await {
+            // the counts, the lists can be long
+            Ok(Value::Object(report)) => {
+                summary["verify"] = report
+                    .into_iter()
+                    .map(|(k, v)| match v {
+                        Value::Array(a) => (k, json!(a.len())),
+                        v => (k, v),
+                    })
+                    .collect();
+            }
+            Ok(_) => {}
+            Err(e) => error = Some(e.to_string()),
+        }
+    }
+    (Some(run.id), summary, error)
+}
+
+/// Print the stored run reports, each with the totals of its windows
+fn report(
+    db: &Db,
+    run: Option<i64>,
+    last: usize,
+    export: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let mut runs = RunReport::load_all(&db.runs);
+    if let Some(id) = run {
+        runs.retain(|r| r.id == id);
+        if runs.is_empty() {
+            return Err(format!("run {id} not found").into());
+        }
+    } else {
+        runs = runs.split_off(runs.len().saturating_sub(last));
+    }
+
+    let runs: Vec<Value> = runs
+        .iter()
+        .map(|r| {
+            let mut v = serde_json::to_value(r).unwrap();
+            v["totals"] = serde_json::to_value(r.totals()).unwrap();
+            v
+        })
+        .collect();
+    let out = serde_json::to_string_pretty(&runs)?;
+    match export {
+        Some(path) => std::fs::write(path, out)?,
+        None => println!("{out}"),
+    }
+
+    Ok(())
+}
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+/// wakes the daemon up from waiting for its next task
+static STOP: Notify = Notify::const_new();
+
+fn shutting_down() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
+
+/// Start [`shutdown_signal`] once, the daemon crawls many times
+fn listen_shutdown() {
+    static LISTENING: Once = Once::new();
+    LISTENING.call_once(|| {
+        tokio::spawn(shutdown_signal());
+    });
+}
+
+/// On SIGINT/SIGTERM, let the running months commit their current page and stop.
+/// A second signal exits immediately.
+async fn shutdown_signal() {
+    loop {
+        wait_signal().await;
+        if SHUTDOWN.swap(true, Ordering::Relaxed) {
+            error!("Forced exit.");
+            std::process::exit(130);
+        }
+        info!("Shutting down after the current pages, press Ctrl-C again to force.");
+        STOP.notify_waiters();
+    }
+}
+
+#[cfg(unix)]
+async fn wait_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut term = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_signal() {
+    tokio::signal::ctrl_c().await.unwrap();
+}
+
+/// Fetch stories one by one from the content API and store them like a crawl does
+async fn fetch_stories(db: &Db, stories: &[String]) -> Result<(), Box<dyn Error>> {
+    for dir in [Kind::Img.dir(), Kind::Media.dir()] {
+        create_dir_all(dir)?;
+    }
+
+    let mut failed = 0;
+    for story in stories {
+        let res = async {
+            let (site, story_ref) = if story.contains('/') {
+                let (site, path) =
+                    site_of_url(story).ok_or_else(|| format!("unknown site of {story}"))?;
+                (site, StoryRef::Path(path))
+            } else {
+                let site = ARGS.sites.first().ok_or("fetching by id needs --sites")?;
+                let site = REGISTRY
+                    .by_id(site)
+                    .ok_or_else(|| format!("unknown site {site}"))?;
+                (site, StoryRef::Id(story))
+            };
+
+            let json = req_story(site, story_ref).await?;
+            let item = serde_json::to_string(&json)?;
+            let objs = site.source.source().objects(&json);
+            download_imgs(db, objs.imgs).await;
+            download_objs(db, objs.media, Kind::Media).await;
+
+            let mut batch = db.keyspace.batch();
+            store_items(db, &mut batch, &site.id, vec![item]);
+            batch.remove(&db.queue, story.as_str());
+            batch.commit()?;
+            Ok::<_, Box<dyn Error>>(())
+        }
+        .await;
+
+        match res {
+            Ok(()) => info!("Fetched {story}"),
+            Err(e) => {
+                error!("Failed to fetch {story}: {e}");
+                failed += 1;
+                if let Some(v) = db.queue.get(story)? {
+                    let mut v: Value = serde_json::from_slice(&v)?;
+                    v["attempts"] = json!(v["attempts"].as_u64().unwrap_or_default() + 1);
+                    v["error"] = json!(e.to_string());
+                    db.queue.insert(story, v.to_string())?;
+                }
+            }
+        }
+    }
+
+    db.save_budget();
+    db.keyspace.persist(PersistMode::SyncAll)?;
+    if failed > 0 {
+        return Err(format!("{failed} of {} stories failed", stories.len()).into());
+    }
+    Ok(())
+}
+
+/// Site and url path of a story url, by the site prefix of the path for Arc sites,
+/// by host for the others
+fn site_of_url(url: &str) -> Option<(&'static Site, &str)> {
+    let url = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default();
+    let (host, path) = match url.find('/') {
+        Some(0) => ("", url),
+        Some(i) => url.split_at(i),
+        None => (url, "/"),
+    };
+    let site = REGISTRY
+        .of_url(path)
+        .or_else(|| REGISTRY.iter().find(|s| s.source.source().host() == host))?;
+    Some((site, path))
+}
+
+/// Story urls queued by `discover`
+fn queued(db: &Db) -> Result<Vec<String>, fjall::Error> {
+    db.queue
+        .keys()
+        .map(|k| Ok(String::from_utf8_lossy(&k?).into_owned()))
+        .collect()
+}
+
+/// Walk the sitemaps and feeds of each site, compare their story urls against `rfa`
+/// and queue the missing ones
+async fn discover(db: &Db, max_documents: usize) -> Result<(), Box<dyn Error>> {
+    let mut reports = vec![];
+    for site in SITES.iter() {
+        let site = REGISTRY
+            .by_id(site)
+            .ok_or_else(|| format!("unknown website {site}"))?;
+        let source = site.source.source();
+        let mut todo: VecDeque<String> = site.discovery_urls().into();
+        let mut visited = HashSet::new();
+        let mut failed_documents = vec![];
+        let mut discovered = HashSet::new();
+        let mut archived = 0;
+        let mut missing = vec![];
+        let mut missing_by_month: BTreeMap<String, u64> = BTreeMap::new();
+
+        while let Some(doc) = todo.pop_front() {
+            if shutting_down() {
+                break;
+            }
+            if visited.len() >= max_documents {
+                error!("{}: stopped after {max_documents} documents", site.id);
+                break;
+            }
+            if !visited.insert(doc.clone()) {
+                continue;
+            }
+            let links = match req_links(&doc).await {
+                Ok(links) => links,
+                Err(e) => {
+                    error!("Failed to discover from {doc}: {e}");
+                    failed_documents.push(json!({ "url": doc, "error": e.to_string() }));
+                    continue;
+                }
+            };
+            todo.extend(
+                links
+                    .documents
+                    .into_iter()
+                    .filter(|d| source.follow_sitemap(d)),
+            );
+
+            let mut batch = db.keyspace.batch();
+            for link in links.stories {
+                if !source.is_story(&link.url)
+                    || site_of_url(&link.url).is_none_or(|(s, _)| s.id != site.id)
+                {
+                    continue;
+                }
+                let Some(website_url) = source::website_url(site, &link.url) else {
+                    continue;
+                };
+                let key = website_url.trim_matches('/').to_owned();
+                if !discovered.insert(key.clone()) {
+                    continue;
+                }
+                if db.rfa.contains_key(&key)? {
+                    archived += 1;
+                    continue;
+                }
+                let month = link.month().unwrap_or_else(|| "unknown".to_owned());
+                *missing_by_month.entry(month).or_default() += 1;
+                if !db.queue.contains_key(&link.url)? {
+                    let v = json!({
+                        "site": site.id,
+                        "lastmod": link.lastmod,
+                        "discovered_in": doc,
+                        "queued": Timestamp::now().to_string(),
+                    });
+                    batch.insert(&db.queue, &link.url, v.to_string());
+                }
+                missing.push(link.url);
+            }
+            batch.commit()?;
+        }
+
+        info!(
+            "{}: {} stories discovered, {} missing",
+            site.id,
+            discovered.len(),
+            missing.len()
+        );
+        reports.push(json!({
+            "site": site.id,
+            "documents": visited.len(),
+            "failed_documents": failed_documents,
+            "discovered": discovered.len(),
+            "archived": archived,
+            "missing": missing.len(),
+            "missing_by_month": missing_by_month,
+            "missing_urls": missing,
+        }));
     }
 
+    let report = json!({ "sites": reports, "queued": db.queue.len()? });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    db.save_budget();
+    db.keyspace.persist(PersistMode::SyncAll)?;
     Ok(())
 }
 
-#[instrument(skip(keyspace, db, done, index))]
+/// Scan `rfa`, `index`, `done`, `replaced` and the downloaded objects for inconsistencies,
+/// returns the report
+async fn verify(db: &Db, repair: bool) -> Result<Value, Box<dyn Error>> {
+    if repair {
+        for dir in [Kind::Img.dir(), Kind::Media.dir()] {
+            create_dir_all(dir)?;
+        }
+    }
+
+    let exists = |url: &str, legacy: bool| match db.blobs.get(url).unwrap() {
+        Some(path) => Path::new(&*String::from_utf8_lossy(&path)).exists(),
+        // images downloaded before the content-addressed store
+        None => legacy && Path::new("imgs").join(get_filename_from_url(url)).exists(),
+    };
+
+    // a refresh running meanwhile would move stories under the scan
+    let mut windows = vec![];
+    for lock in SITE_LOCKS.values() {
+        windows.push(lock.read().await);
+    }
+
+    info!("Checking articles");
+    let mut articles = 0;
+    let mut missing_index = vec![];
+    let mut missing = Objects::default();
+    for kv in db.rfa.iter() {
+        let (k, v) = kv?;
+        articles += 1;
+        let website_url = String::from_utf8_lossy(&k);
+        let json = db.codec.value(&v)?;
+        if let Some(display_date) = json["display_date"].as_str()
+            && let Some(key) = story_index_key(&website_url, display_date)
+            && !db.index.contains_key(&key)?
+        {
+            missing_index.push((website_url.to_string(), key));
+        }
+
+        let site = REGISTRY
+            .of_url(&website_url)
+            .unwrap_or_else(|| REGISTRY.first());
+        let objs = site.source.source().objects(&json);
+        missing
+            .imgs
+            .extend(objs.imgs.into_iter().filter(|img| !exists(&img.url, true)));
+        missing
+            .media
+            .extend(objs.media.into_iter().filter(|url| !exists(url, false)));
+    }
+    missing.imgs.sort_unstable_by(|a, b| a.url.cmp(&b.url));
+    missing.imgs.dedup_by(|a, b| a.url == b.url);
+    missing.media.sort_unstable();
+    missing.media.dedup();
+
+    info!("Checking index");
+    let mut dangling_index = vec![];
+    let mut unknown_sites = BTreeMap::new();
+    for kv in db.index.iter() {
+        let (k, _) = kv?;
+        let rest = String::from_utf8_lossy(&k[9..]);
+        // a site dropped from the registry is not a reason to lose its index
+        let Some(site) = REGISTRY.by_code(k[0]) else {
+            *unknown_sites.entry(k[0]).or_insert(0) += 1;
+            continue;
+        };
+        if !db.rfa.contains_key(format!("{}/{rest}", site.prefix))? {
+            dangling_index.push(k);
+        }
+    }
+    for (code, count) in &unknown_sites {
+        warn!("{count} index entries of unknown site code {code}, left alone");
+    }
+
+    info!("Checking done markers");
+    let mut empty_done = vec![];
+    let mut window_counts = None;
+    for kv in db.done.iter() {
+        let (k, v) = kv?;
+        let done_key = String::from_utf8_lossy(&k).into_owned();
+        let mut parts = done_key.rsplitn(3, '-');
+        let (Some(month), Some(year), Some(site)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let (Ok(year), Ok(month), Some(site)) = (year.parse(), month.parse(), REGISTRY.by_id(site))
+        else {
+            continue;
+        };
+        // months the API had no stories for are marked done on purpose
+        let count = match <[u8; 8]>::try_from(&v[..]) {
+            Ok(count) => Some(u64::from_be_bytes(count)),
+            // markers from before they held the count, unknown if no run tells
+            Err(_) => window_counts
+                .get_or_insert_with(|| month_counts(db))
+                .get(&(site.id.clone(), date(year, month, 1)))
+                .copied(),
+        };
+        if count.is_none_or(|count| count == 0) {
+            continue;
+        }
+        let code = site.code;
+        let begin = date(year, month, 1).to_zoned(TimeZone::UTC)?.timestamp();
+        let end = begin
+            .to_zoned(TimeZone::UTC)
+            .saturating_add(1.month())
+            .timestamp();
+        let mut lo = vec![code];
+        lo.extend_from_slice(&begin.as_second().to_be_bytes());
+        let mut hi = vec![code];
+        hi.extend_from_slice(&end.as_second().to_be_bytes());
+        if db.index.range(lo..hi).next().is_none() {
+            empty_done.push(done_key);
+        }
+    }
+
+    info!("Checking failed downloads");
+    let mut given_up = vec![];
+    for kv in db.failed.iter() {
+        let (k, v) = kv?;
+        let v: Value = serde_json::from_slice(&v).unwrap_or_default();
+        if v["attempts"].as_u64().unwrap_or_default() >= MAX_ATTEMPTS {
+            given_up.push(String::from_utf8_lossy(&k).into_owned());
+        }
+    }
+
+    info!("Checking replaced blobs");
+    let mut unused_blobs = vec![];
+    let mut reused_blobs = vec![];
+    if !db.replaced.is_empty()? {
+        let mut used = HashSet::new();
+        for kv in db.blobs.iter() {
+            used.insert(kv?.1);
+        }
+        for kv in db.replaced.iter() {
+            let (k, _) = kv?;
+            if used.contains(&k) {
+                reused_blobs.push(k);
+            } else {
+                unused_blobs.push(k);
+            }
+        }
+    }
+
+    let report = json!({
+        "articles": articles,
+        "missing_index": missing_index.iter().map(|(url, _)| url).collect::<Vec<_>>(),
+        "dangling_index": dangling_index.len(),
+        "unknown_sites": unknown_sites,
+        "missing_imgs": missing.imgs.iter().map(|img| &img.url).collect::<Vec<_>>(),
+        "missing_media": missing.media,
+        "empty_done": empty_done,
+        "failed_downloads": given_up,
+        "unused_blobs": unused_blobs
+            .iter()
+            .map(|k| String::from_utf8_lossy(k))
+            .collect::<Vec<_>>(),
+    });
+
+    if repair {
+        info!("Repairing");
+        let mut batch = db.keyspace.batch();
+        for (_, key) in missing_index {
+            batch.insert(&db.index, key, []);
+        }
+        for key in dangling_index {
+            batch.remove(&db.index, key);
+        }
+        for key in empty_done {
+            batch.remove(&db.done, key);
+        }
+        for key in unused_blobs {
+            match std::fs::remove_file(&*String::from_utf8_lossy(&key)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    error!("Failed to delete {}: {e}", String::from_utf8_lossy(&key));
+                    continue;
+                }
+            }
+            batch.remove(&db.replaced, key);
+        }
+        for key in reused_blobs {
+            batch.remove(&db.replaced, key);
+        }
+        batch.commit()?;
+        drop(windows);
+
+        let imgs = download_imgs(db, missing.imgs).await;
+        let media = download_objs(db, missing.media, Kind::Media).await;
+        info!(
+            "Re-downloaded {} images and {} media, {} failed",
+            imgs.downloaded,
+            media.downloaded,
+            imgs.failed + media.failed
+        );
+        db.save_budget();
+        db.keyspace.persist(PersistMode::SyncAll)?;
+    }
+
+    Ok(report)
+}
+
+/// API count of the months in the run reports, the latest complete crawl of each
+fn month_counts(db: &Db) -> HashMap<(String, Date), u64> {
+    let mut counts = HashMap::new();
+    for run in RunReport::load_all(&db.runs) {
+        for w in run.windows.into_iter().filter(|w| w.complete) {
+            if let (Ok(begin), Ok(end)) = (w.begin.parse::<Date>(), w.end.parse::<Date>())
+                && begin.day() == 1
+                && end == begin.last_of_month()
+            {
+                counts.insert((w.site, begin), w.count);
+            }
+        }
+    }
+    counts
+}
+
+/// Recompress the stories of every site with a dictionary trained on a random
+/// sample of them. Sites with too few stories to train on get plain zstd.
+fn compress(db: &Db, max_samples: usize) -> Result<(), Box<dyn Error>> {
+    for site in REGISTRY.iter() {
+        let prefix = format!("{}/", site.prefix);
+        let mut samples: Vec<Vec<u8>> = vec![];
+        for (seen, kv) in db.rfa.prefix(&prefix).enumerate() {
+            let (_, v) = kv?;
+            let json = db.codec.decode(&v)?.into_owned();
+            // reservoir sampling
+            if samples.len() < max_samples {
+                samples.push(json);
+            } else {
+                let i = fastrand::usize(..=seen);
+                if i < max_samples {
+                    samples[i] = json;
+                }
+            }
+        }
+        if samples.is_empty() {
+            continue;
+        }
+        match db.codec.train(&site.id, &samples) {
+            Ok(id) => info!(
+                "Trained dictionary {id} of {} on {} stories",
+                site.id,
+                samples.len()
+            ),
+            Err(e) => warn!("No dictionary for {}: {e}", site.id),
+        }
+        drop(samples);
+
+        let (mut before, mut after) = (0, 0);
+        for partition in [&db.rfa, &db.revisions] {
+            let mut batch = db.keyspace.batch();
+            for kv in partition.prefix(&prefix) {
+                let (k, v) = kv?;
+                let json = db.codec.decode(&v)?;
+                let compressed = db.codec.encode(&site.id, &json)?;
+                before += v.len();
+                after += compressed.len();
+                batch.insert(partition, k, compressed);
+                if batch.len() >= 1000 {
+                    std::mem::replace(&mut batch, db.keyspace.batch()).commit()?;
+                }
+            }
+            batch.commit()?;
+        }
+        info!(
+            "Compressed {}: {} MB -> {} MB",
+            site.id,
+            before / 1_000_000,
+            after / 1_000_000
+        );
+    }
+    db.keyspace.persist(PersistMode::SyncAll)?;
+
+    // give the space of the replaced values back
+    for partition in [&db.rfa, &db.revisions] {
+        partition.gc_scan()?;
+        let freed = partition.gc_with_space_amp_target(1.5)?;
+        info!("Freed {} MB of {}", freed / 1_000_000, partition.name);
+    }
+    Ok(())
+}
+
+/// Index key of a story, `None` if its `website_url` belongs to no registered site
+/// or the key can't be built from it and `display_date`
+fn story_index_key(website_url: &str, display_date: &str) -> Option<Vec<u8>> {
+    let Some(site) = REGISTRY.of_url(website_url) else {
+        error!("No registered site for {website_url}");
+        return None;
+    };
+    let key = index_key(site.code, website_url, display_date);
+    if key.is_none() {
+        error!("No index key for {website_url} of {display_date}");
+    }
+    key
+}
+
+/// Handles of the partitions in `rfa.db`
+#[derive(Clone)]
+struct Db {
+    keyspace: Keyspace,
+    rfa: PartitionHandle,
+    index: PartitionHandle,
+    /// `<site>-<year>-<month>` of the months crawled -> their API count
+    done: PartitionHandle,
+    /// url -> last download error, retried on the next run
+    failed: PartitionHandle,
+    /// url -> content-addressed blob path, see [`blob_path`]
+    blobs: PartitionHandle,
+    /// blob paths a larger original replaced, deleted by `verify --repair` unless
+    /// another url still refers to them
+    replaced: PartitionHandle,
+    /// superseded versions of stories, see [`revision_key`]
+    revisions: PartitionHandle,
+    /// `done` key of a month in progress -> offset of the next page
+    progress: PartitionHandle,
+    /// UTC day -> requests sent, see [`Throttle`]
+    budget: PartitionHandle,
+    /// crawl run reports, see [`RunReport`]
+    runs: PartitionHandle,
+    /// story url -> where `discover` found it, until fetched
+    queue: PartitionHandle,
+    /// site id and section id -> section, see [`Section`]
+    sections: PartitionHandle,
+    /// image url -> its source, size and credit, see [`Image`]
+    images: PartitionHandle,
+    /// site id and slug -> author, see [`author_key`]
+    authors: PartitionHandle,
+    /// stories of each author, see [`byline_key`]
+    bylines: PartitionHandle,
+    /// compresses the stories of `rfa` and `revisions` with the dictionaries of `dicts`
+    codec: Codec,
+}
+
+impl Db {
+    fn open() -> Result<Self, fjall::Error> {
+        let keyspace = Config::new("rfa.db").open()?;
+        let rfa = keyspace.open_partition("rfa", kv_sep_partition_option())?;
+        let index = keyspace.open_partition("index", PartitionCreateOptions::default())?;
+        let done = keyspace.open_partition("done", PartitionCreateOptions::default())?;
+        let failed = keyspace.open_partition("failed", PartitionCreateOptions::default())?;
+        let blobs = keyspace.open_partition("blobs", PartitionCreateOptions::default())?;
+        let replaced = keyspace.open_partition("replaced", PartitionCreateOptions::default())?;
+        let revisions = keyspace.open_partition("revisions", kv_sep_partition_option())?;
+        let progress = keyspace.open_partition("progress", PartitionCreateOptions::default())?;
+        let budget = keyspace.open_partition("budget", PartitionCreateOptions::default())?;
+        let runs = keyspace.open_partition("runs", PartitionCreateOptions::default())?;
+        let queue = keyspace.open_partition("queue", PartitionCreateOptions::default())?;
+        let sections = keyspace.open_partition("sections", PartitionCreateOptions::default())?;
+        let images = keyspace.open_partition("images", PartitionCreateOptions::default())?;
+        let authors = keyspace.open_partition("authors", PartitionCreateOptions::default())?;
+        let bylines = keyspace.open_partition("bylines", PartitionCreateOptions::default())?;
+        let dicts = keyspace.open_partition("dicts", PartitionCreateOptions::default())?;
+        let codec = Codec::new(dicts);
+        Ok(Self {
+            keyspace,
+            rfa,
+            index,
+            done,
+            failed,
+            blobs,
+            replaced,
+            revisions,
+            progress,
+            budget,
+            runs,
+            queue,
+            sections,
+            images,
+            authors,
+            bylines,
+            codec,
+        })
+    }
+
+    fn save_budget(&self) {
+        let (day, n) = THROTTLE.used();
+        self.budget
+            .insert(day.to_string(), n.to_be_bytes())
+            .unwrap();
+    }
+}
+
+/// Crawl a month unless already `done`, returns its report if crawled
+#[instrument(skip(db, run))]
 async fn fetch_articles(
-    keyspace: &Keyspace,
-    db: &PartitionHandle,
-    done: &PartitionHandle,
-    index: &PartitionHandle,
+    db: &Db,
+    run: &RunReport,
     site: &str,
     year: i16,
     month: i8,
-) -> Result<(), Box<dyn Error>> {
+) -> Option<WindowReport> {
     let done_key = format!("{site}-{year}-{month}");
-    if done.contains_key(&done_key).unwrap() {
+    if !ARGS.recrawl && db.done.contains_key(&done_key).unwrap() {
         info!("Already download.");
-        return Ok(());
+        return None;
     }
 
     let begin = date(year, month, 1);
     let end = begin.last_of_month();
-    let offset = 0;
-    let json = req_story_archive(site, offset, &begin, &end).await?;
+    let mut report = WindowReport::new(site, begin, end);
+    let started = Instant::now();
+
+    let res = match site_lock(site) {
+        Ok(lock) => {
+            let _window = lock.read().await;
+            crawl_window(db, site, &begin, &end, Some(&done_key), &mut report).await
+        }
+        Err(e) => Err(e.into()),
+    };
+    report.complete = matches!(res, Ok(true));
+    match res {
+        Ok(true) => {
+            let mut batch = db.keyspace.batch();
+            batch.remove(&db.progress, &done_key);
+            for kv in db.progress.prefix(format!("{done_key}@")) {
+                batch.remove(&db.progress, kv.unwrap().0);
+            }
+            // the current month is still growing
+            let growing = end >= Zoned::now().date();
+            // the count tells `verify` an empty month is not a failed crawl
+            if !(growing || report.count == 0 && year >= 2024) {
+                batch.insert(&db.done, &done_key, report.count.to_be_bytes());
+            }
+            batch.commit().unwrap();
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!("Failed to crawl {done_key}: {e}");
+            report.errors.push(e.to_string());
+        }
+    }
 
-    let count = json["count"].as_u64().unwrap();
-    info!("Total articles found: {}", count);
+    report.duration_ms = started.elapsed().as_millis() as u64;
+    run.save_window(&db.runs, &report);
+    Some(report)
+}
+
+/// Re-query a recent window regardless of `done` markers, so stories of the
+/// current month and later edits get archived.
+#[instrument(skip(db, run))]
+async fn refresh(db: &Db, run: &RunReport, site: &str, begin: &Date, end: &Date) -> WindowReport {
+    let mut report = WindowReport::new(site, begin, end);
+    let started = Instant::now();
 
-    if count == 0 {
-        if year < 2024 {
-            done.insert(&done_key, []).unwrap();
+    let res = match site_lock(site) {
+        Ok(lock) => {
+            let _window = lock.write().await;
+            crawl_window(db, site, begin, end, None, &mut report).await
         }
-        return Ok(());
+        Err(e) => Err(e.into()),
+    };
+    match res {
+        Ok(complete) => report.complete = complete,
+        Err(e) => {
+            error!("Failed to refresh {site}: {e}");
+            report.errors.push(e.to_string());
+        }
+    }
+
+    report.duration_ms = started.elapsed().as_millis() as u64;
+    run.save_window(&db.runs, &report);
+    report
+}
+
+/// Crawl `[begin, end]` page by page into `report`, returns whether it reached
+/// the end, `false` if interrupted by a shutdown.
+///
+/// Pages follow the `next` cursor of the API and stories are deduped by `_id`, so
+/// a result set shifting between requests neither loops nor double-counts. A
+/// window with more stories than the backend can page through is split into
+/// weeks, then days.
+///
+/// Each page downloads its objects, then commits its items in one batch. With a
+/// `progress_key`, the cursor of the next page is committed in the same batch, so
+/// an interrupted crawl resumes exactly where it stopped.
+async fn crawl_window(
+    db: &Db,
+    site: &str,
+    begin: &Date,
+    end: &Date,
+    progress_key: Option<&str>,
+    report: &mut WindowReport,
+) -> Result<bool, Box<dyn Error>> {
+    let mut offset = progress_key
+        .and_then(|k| db.progress.get(k).unwrap())
+        .map(|v| u64::from_be_bytes(v[..].try_into().unwrap()))
+        .unwrap_or_default();
+    if offset > 0 {
+        info!("Resuming from offset {}", offset);
     }
 
-    let (mut items, mut imgs) = extract(&json);
+    let source = REGISTRY
+        .by_id(site)
+        .ok_or_else(|| format!("unknown website {site}"))?
+        .source
+        .source();
+    let mut seen = HashSet::new();
+    let mut fetched = 0;
+    let mut first = true;
+    loop {
+        let query = Query {
+            begin: *begin,
+            end: *end,
+            offset,
+            size: SIZE,
+        };
+        let listing = req_list(site, &query).await?;
+        let count = listing.count;
+        if first {
+            info!("Total articles found: {:?}", count);
+            if let (Some(count), Some(max)) = (count, source.max_window())
+                && count > max
+                && begin < end
+            {
+                return Box::pin(crawl_split(db, site, begin, end, progress_key, report)).await;
+            }
+            report.count += count.unwrap_or_default();
+            first = false;
+        }
+
+        // a page can repeat stories of earlier ones, the raw size tells the end
+        let raw = listing.stories.len() as u64;
+        let (items, objs) = extract(source, listing.stories, &mut seen);
+        let n = items.len() as u64;
+        fetched += n;
+        report.fetched += n;
+        if count.is_none() {
+            report.count += n;
+        }
+        report.imgs.add(&download_imgs(db, objs.imgs).await);
+        report
+            .media
+            .add(&download_objs(db, objs.media, Kind::Media).await);
+
+        let next = listing.next.filter(|next| *next > offset);
+        offset = next.unwrap_or(offset + raw);
+
+        let mut batch = db.keyspace.batch();
+        report.stored += store_items(db, &mut batch, site, items) as u64;
+        if let Some(k) = progress_key {
+            batch.insert(&db.progress, k, offset.to_be_bytes());
+        }
+        let (day, used) = THROTTLE.used();
+        batch.insert(&db.budget, day.to_string(), used.to_be_bytes());
+        batch.commit().unwrap();
+
+        // an empty page or one without a next offset would never advance
+        if next.is_none() || raw == 0 || count.is_some_and(|count| offset >= count) {
+            info!(
+                "Total articles fetched: {fetched}, new or changed: {}",
+                report.stored
+            );
+            return Ok(true);
+        }
+        if let Some(max) = source.max_window()
+            && offset >= max
+        {
+            error!("{begin} to {end} exceeds the result window, stopped at {offset}");
+            report.errors.push(format!(
+                "{begin} to {end} truncated at {offset} of {}",
+                count.unwrap_or_default()
+            ));
+            return Ok(true);
+        }
+        if shutting_down() {
+            info!("Interrupted at offset {}", offset);
+            return Ok(false);
+        }
+    }
+}
 
-    while count > items.len() as u64 {
-        let offset = items.len() as u64;
-        let json = req_story_archive(site, offset, &begin, &end).await?;
-        let (items2, imgs2) = extract(&json);
+/// Crawl `[begin, end]` as consecutive weeks, or days if it's a week at most.
+/// Each part checkpoints under its own key `<progress_key>@<begin>`.
+async fn crawl_split(
+    db: &Db,
+    site: &str,
+    begin: &Date,
+    end: &Date,
+    progress_key: Option<&str>,
+    report: &mut WindowReport,
+) -> Result<bool, Box<dyn Error>> {
+    let step = if end.since(*begin)?.get_days() >= 7 {
+        1.week()
+    } else {
+        1.day()
+    };
+    info!("Splitting {begin} to {end} by {step:#}");
 
-        items.extend(items2);
-        imgs.extend(imgs2);
+    let mut part = *begin;
+    while part <= *end {
+        let part_end = part.saturating_add(step).yesterday()?.min(*end);
+        let part_key = progress_key.map(|k| format!("{k}@{part}"));
+        if !crawl_window(db, site, &part, &part_end, part_key.as_deref(), report).await? {
+            return Ok(false);
+        }
+        part = part_end.tomorrow()?;
     }
 
-    info!("Total articles fetched: {}", items.len());
+    Ok(true)
+}
 
-    for img in imgs {
-        let img_name = get_filename_from_url(&img);
-        let img_path = PathBuf::from("imgs");
-        let img_path = img_path.join(img_name);
+/// Kind of a downloaded object, deciding its folder and accepted content types
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Img,
+    /// audio and video
+    Media,
+}
 
-        if !Path::new(&img_path).exists() {
-            // if failed, just rerun, better controlled by systemds
-            dl_obj(&img, &img_path).await.unwrap();
-            info!("Downloaded image: {}", img);
-        } else {
-            info!("Image already exists: {}", img_path.display());
+impl Kind {
+    fn dir(self) -> &'static str {
+        match self {
+            Kind::Img => "imgs",
+            Kind::Media => "media",
         }
     }
+}
 
-    let mut batch = keyspace.batch();
+async fn download_objs(db: &Db, mut urls: Vec<String>, kind: Kind) -> ObjStats {
+    urls.sort_unstable();
+    urls.dedup();
+    let stats = Mutex::new(ObjStats::default());
+    stream::iter(urls)
+        .for_each_concurrent(None, |url| {
+            let stats = &stats;
+            async move {
+                if let Some(path) = db.blobs.get(&url).unwrap()
+                    && Path::new(&*String::from_utf8_lossy(&path)).exists()
+                {
+                    info!("Already exists: {}", String::from_utf8_lossy(&path));
+                    stats.lock().unwrap().skipped += 1;
+                } else if download_obj(db, &url, &url, kind).await.is_some() {
+                    stats.lock().unwrap().downloaded += 1;
+                } else {
+                    stats.lock().unwrap().failed += 1;
+                }
+            }
+        })
+        .await;
+    stats.into_inner().unwrap()
+}
+
+/// Download images from the source of their original, see [`Image`].
+///
+/// An archived copy smaller than the original the story tells of is replaced, unless
+/// it already came from the same source.
+async fn download_imgs(db: &Db, mut imgs: Vec<Image>) -> ObjStats {
+    // an image listed twice keeps the entry telling its size
+    imgs.sort_unstable_by(|a, b| a.url.cmp(&b.url).then(b.width.cmp(&a.width)));
+    imgs.dedup_by(|a, b| a.url == b.url);
+    let stats = Mutex::new(ObjStats::default());
+    stream::iter(imgs)
+        .for_each_concurrent(None, |img| {
+            let stats = &stats;
+            async move {
+                let archived = db
+                    .blobs
+                    .get(&img.url)
+                    .unwrap()
+                    .map(|path| String::from_utf8_lossy(&path).into_owned())
+                    .filter(|path| Path::new(path).exists());
+                let blob = match archived {
+                    Some(path) if !needs_original(db, &img, &path) => {
+                        info!("Already exists: {path}");
+                        stats.lock().unwrap().skipped += 1;
+                        None
+                    }
+                    _ => match download_obj(db, &img.url, &img.src, Kind::Img).await {
+                        Some(blob) => {
+                            stats.lock().unwrap().downloaded += 1;
+                            // the smaller copy may be shared, `verify` deletes it if not
+                            if let Some(old) = archived.filter(|old| *old != blob) {
+                                db.replaced.insert(old, []).unwrap();
+                            }
+                            Some(blob)
+                        }
+                        None => {
+                            stats.lock().unwrap().failed += 1;
+                            None
+                        }
+                    },
+                };
+                save_image_meta(db, &img, blob.as_deref());
+            }
+        })
+        .await;
+    stats.into_inner().unwrap()
+}
+
+/// Whether the archived copy of `img` at `path` is a smaller variant of its original
+fn needs_original(db: &Db, img: &Image, path: &str) -> bool {
+    let from_src = db
+        .images
+        .get(&img.url)
+        .unwrap()
+        .and_then(|v| serde_json::from_slice::<Value>(&v).ok())
+        .is_some_and(|meta| meta["src"] == img.src);
+    if from_src {
+        return false;
+    }
+    match (img.width, imagesize::size(path)) {
+        (Some(width), Ok(size)) => (size.width as u64) < width,
+        _ => false,
+    }
+}
+
+/// Record the source, credit and, once downloaded, the size of an image in `images`
+fn save_image_meta(db: &Db, img: &Image, blob: Option<&str>) {
+    let mut meta = db
+        .images
+        .get(&img.url)
+        .unwrap()
+        .and_then(|v| serde_json::from_slice::<Value>(&v).ok())
+        .unwrap_or_else(|| json!({}));
+    if let Some(credit) = &img.credit {
+        meta["credit"] = json!(credit);
+    }
+    if let Some(blob) = blob {
+        meta["src"] = json!(img.src);
+        if let Ok(size) = imagesize::size(blob) {
+            meta["width"] = json!(size.width);
+            meta["height"] = json!(size.height);
+        }
+    }
+    if meta.as_object().is_some_and(|m| !m.is_empty()) {
+        db.images.insert(&img.url, meta.to_string()).unwrap();
+    }
+}
+
+/// Download one object from `src` as `url`, recording the failure in `failed`
+/// instead of aborting the crawl. Returns its blob path.
+async fn download_obj(db: &Db, url: &str, src: &str, kind: Kind) -> Option<String> {
+    let _permit = DL_PERMITS.acquire().await.unwrap();
+    match dl_obj(src, kind).await {
+        Ok(path) => {
+            info!("Downloaded: {} -> {}", src, path);
+            db.blobs.insert(url, &path).unwrap();
+            db.failed.remove(url).unwrap();
+            Some(path)
+        }
+        Err(e) => {
+            error!("Failed to download {url}: {e}");
+            let attempts = db
+                .failed
+                .get(url)
+                .unwrap()
+                .and_then(|v| serde_json::from_slice::<Value>(&v).ok())
+                .and_then(|v| v["attempts"].as_u64())
+                .unwrap_or_default();
+            let v = json!({
+                "kind": kind.dir(),
+                "src": src,
+                "error": e.to_string(),
+                "attempts": attempts + 1,
+                "last_attempt": Timestamp::now().to_string(),
+            });
+            db.failed.insert(url, v.to_string()).unwrap();
+            None
+        }
+    }
+}
+
+/// Retry the downloads failed in previous runs, up to [`MAX_ATTEMPTS`] times
+async fn retry_failed(db: &Db) {
+    let mut imgs = vec![];
+    let mut media = vec![];
+    let mut given_up = vec![];
+    for kv in db.failed.iter() {
+        let (k, v) = kv.unwrap();
+        let v: Value = serde_json::from_slice(&v).unwrap_or_default();
+        let url = String::from_utf8_lossy(&k).into_owned();
+        if v["attempts"].as_u64().unwrap_or_default() >= MAX_ATTEMPTS {
+            given_up.push(url);
+            continue;
+        }
+        match v["kind"].as_str() {
+            Some("media") => media.push(url),
+            _ => {
+                // failures recorded before the sources of originals lack `src`, a
+                // relative url is on the host of the site it belongs to
+                let host = REGISTRY
+                    .of_url(&url)
+                    .map(|site| site.source.source().host())
+                    .unwrap_or_default();
+                if v["src"].is_null() && host.is_empty() && !url.starts_with("http") {
+                    warn!("Not retrying {url}, no site tells its host");
+                    continue;
+                }
+                let mut img = Image::plain(&url, &host);
+                if let Some(src) = v["src"].as_str() {
+                    img.src = src.to_owned();
+                }
+                imgs.push(img);
+            }
+        }
+    }
+    if !given_up.is_empty() {
+        warn!(
+            "Not retrying {} downloads failed {MAX_ATTEMPTS} times: {}",
+            given_up.len(),
+            given_up.join(", ")
+        );
+    }
+    if imgs.is_empty() && media.is_empty() {
+        return;
+    }
+
+    info!("Retrying {} failed downloads", imgs.len() + media.len());
+    download_imgs(db, imgs).await;
+    download_objs(db, media, Kind::Media).await;
+}
+
+/// Upsert items into `rfa`, compressed, and `index`, returns the number of new or changed items.
+/// Unchanged items are skipped, and a stale index entry is dropped if `display_date` moved.
+/// Edited stories move their previous version into `revisions`. Their credits update
+/// `authors` and `bylines`.
+fn store_items(db: &Db, batch: &mut Batch, site: &str, items: Vec<String>) -> usize {
+    let mut changed = 0;
+    let mut authors: BTreeMap<String, Author> = BTreeMap::new();
     for i in items {
         let json: Value = serde_json::from_str(&i).unwrap();
-        let website_url = json["websites"][site]["website_url"]
-            .as_str()
-            .unwrap()
-            .trim_matches('/');
+        let (Some(website_url), Some(display_date)) = (
+            json["websites"][site]["website_url"].as_str(),
+            json["display_date"].as_str(),
+        ) else {
+            warn!(
+                "Skipping story {} of {site} without website_url or display_date",
+                json["_id"]
+            );
+            continue;
+        };
+        // the index and the web order stories by it
+        if display_date.parse::<Timestamp>().is_err() {
+            warn!(
+                "Skipping story {} of {site} with display_date {display_date}",
+                json["_id"]
+            );
+            continue;
+        }
+        let website_url = website_url.trim_matches('/');
+
+        if let Some(old) = db.rfa.get(website_url).unwrap() {
+            let old = db.codec.decode(&old).unwrap();
+            if *old == *i.as_bytes() {
+                continue;
+            }
+            let old_json: Value = serde_json::from_slice(&old).unwrap();
+            if let Some(old_date) = old_json["display_date"].as_str()
+                && old_date != display_date
+                && let Some(key) = story_index_key(website_url, old_date)
+            {
+                batch.remove(&db.index, key);
+            }
+            if let Some(old_date) = old_json["display_date"].as_str() {
+                for author in Author::of_story(&old_json, site) {
+                    if let Some(key) = byline_key(site, &author.slug, website_url, old_date) {
+                        batch.remove(&db.bylines, key);
+                    }
+                }
+            }
+            // keep every superseded version, an edit without a new `last_updated_date`
+            // must not replace an earlier revision
+            let mut ts = revision_ts(&old_json);
+            while db
+                .revisions
+                .contains_key(revision_key(website_url, ts))
+                .unwrap()
+            {
+                ts = ts.checked_add(1.second()).unwrap();
+            }
+            info!("New revision of {website_url}");
+            let old = db.codec.encode(site, &old).unwrap();
+            batch.insert(&db.revisions, revision_key(website_url, ts), old);
+        }
 
-        batch.insert(db, website_url, i);
+        batch.insert(
+            &db.rfa,
+            website_url,
+            db.codec.encode(site, i.as_bytes()).unwrap(),
+        );
 
-        let display_date = json["display_date"].as_str().unwrap();
-        let index_key = index_key(website_url, display_date);
+        if let Some(index_key) = story_index_key(website_url, display_date) {
+            batch.insert(&db.index, index_key, []);
+        }
+        for author in Author::of_story(&json, site) {
+            if let Some(key) = byline_key(site, &author.slug, website_url, display_date) {
+                batch.insert(&db.bylines, key, []);
+            }
+            match authors.get_mut(&author.slug) {
+                Some(known) => known.merge(author),
+                None => {
+                    let mut known = Author::load(&db.authors, site, &author.slug)
+                        .unwrap_or_else(|| author.clone());
+                    known.merge(author);
+                    authors.insert(known.slug.clone(), known);
+                }
+            }
+        }
+        changed += 1;
+    }
 
-        batch.insert(index, index_key, []);
+    for (slug, author) in authors {
+        let key = author_key(site, &slug);
+        batch.insert(&db.authors, key, serde_json::to_vec(&author).unwrap());
     }
-    batch.commit().unwrap();
+    changed
+}
 
-    done.insert(&done_key, []).unwrap();
+/// All requests go through here to honor the [`Throttle`], and `--record`/`--replay`
+/// GET `url`, written to the WARC files if enabled
+async fn get(url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    capture(url, send(url).await?).await
+}
 
-    Ok(())
+/// Write a response to the WARC files, and hand out a copy of it
+async fn capture(url: &str, resp: reqwest::Response) -> Result<reqwest::Response, reqwest::Error> {
+    let Some(warc) = &*WARC else {
+        return Ok(resp);
+    };
+    let (status, version, headers) = (resp.status(), resp.version(), resp.headers().clone());
+    let body = resp.bytes().await?;
+    if let Err(e) = warc.write(url, status, version, &headers, &mut Cursor::new(&body)) {
+        error!("Failed to write the WARC record of {url}: {e}");
+    }
+    Ok(rebuild(status, headers, body))
 }
 
+/// GET `url`, from the origin, a proxy or the replay server, recorded if enabled
+async fn send(url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    let resp = match (&ARGS.replay, &*PROXIES) {
+        (Some(addr), _) => {
+            THROTTLE.request().await;
+            CLIENT.get(replay::replay_url(addr, url)).send().await?
+        }
+        // every attempt through another proxy is a request of its own
+        (None, Some(pool)) => pool.get(url, async || THROTTLE.request().await).await?,
+        (None, None) => {
+            THROTTLE.request().await;
+            CLIENT.get(url).send().await?
+        }
+    };
+    match &*RECORD_DIR {
+        Some(dir) => record(dir, url, resp).await,
+        None => Ok(resp),
+    }
+}
+
+/// Save a response as a fixture, and hand out a copy of it
+async fn record(
+    dir: &Path,
+    url: &str,
+    resp: reqwest::Response,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = resp.bytes().await?;
+    if let Err(e) = replay::save(dir, url, status.as_u16(), &headers, &body) {
+        error!("Failed to record {url}: {e}");
+    }
+    Ok(rebuild(status, headers, body))
+}
+
+/// A response of a body already read
+fn rebuild(
+    status: StatusCode,
+    headers: HeaderMap,
+    body: impl Into<reqwest::Body>,
+) -> reqwest::Response {
+    let mut copy = http::Response::new(body);
+    *copy.status_mut() = status;
+    *copy.headers_mut() = headers;
+    copy.into()
+}
+
+/// List a page of stories of `site`
 #[instrument]
-async fn req_story_archive(
-    site: &str,
-    offset: u64,
-    begin: &Date,
-    end: &Date,
-) -> Result<Value, Box<dyn Error>> {
-    let query_json = json!({
-        "feature": "results-list",
-        "offset": offset,
-        "query": format!("display_date:[{} TO {}]", begin, end),
-        "size": SIZE
-    });
-    let query_json = query_json.to_string();
-    let encoded_query = encode(&query_json);
-    let filter = format!(
-        r#"{{content_elements{{_id,credits{{by{{additional_properties{{original{{byline}}}},name,type,url}}}},description{{basic}},display_date,headlines{{basic}},label{{basic{{display,text,url}}}},owner{{sponsored}},promo_items{{basic{{_id,auth{{1}},type,url,caption}},lead_art{{promo_items{{basic{{_id,auth{{1}},type,url}}}}}},type}},type,websites{{{}{{website_section{{_id,name}},website_url}}}},content_elements{{type,content,url,caption{{basic}}}}}},count,next}}"#,
-        site
-    );
-    let filter = encode(&filter);
+async fn req_list(site: &str, query: &Query) -> Result<Listing, Box<dyn Error>> {
+    let site = REGISTRY
+        .by_id(site)
+        .ok_or_else(|| format!("unknown website {site}"))?;
+    let source = site.source.source();
+    let resp = get(&source.list_url(site, query)).await?;
+    let status = resp.status();
+    info!("Status: {status}");
+    if !status.is_success() {
+        return Err(format!("unexpected status {status}").into());
+    }
+    let headers = resp.headers().clone();
+    let body = resp.bytes().await?;
+    source.parse_list(site, query, &headers, &body)
+}
 
-    let url = format!(
-        "https://www.rfa.org/pf/api/v3/content/fetch/story-feed-query?query={}&filter={}&d=147&mxId=00000000&_website={}",
-        encoded_query, filter, site
-    );
-    let resp = CLIENT.get(url).send().await?;
+/// Story and sitemap urls of a sitemap or feed
+#[instrument]
+async fn req_links(url: &str) -> Result<Links, Box<dyn Error>> {
+    let resp = get(url).await?;
+    let status = resp.status();
+    info!("Status: {status}");
+    if !status.is_success() {
+        return Err(format!("unexpected status {status}").into());
+    }
+    discover::parse(&resp.bytes().await?)
+}
+
+/// Replace the stored sections of a site by its current hierarchy.
+///
+/// On failure the previous ones are kept, the taxonomy rarely changes.
+#[instrument(skip_all, fields(site = site.id))]
+async fn fetch_sections(db: &Db, site: &Site) {
+    let source = site.source.source();
+    let Some(url) = source.sections_url(site) else {
+        return;
+    };
+    let sections = async {
+        let resp = get(&url).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(format!("unexpected status {status}").into());
+        }
+        source.parse_sections(site, &resp.bytes().await?)
+    };
+    let sections: Vec<Section> = match sections.await {
+        Ok(sections) if !sections.is_empty() => sections,
+        Ok(_) => return error!("No sections"),
+        Err(e) => return error!("Failed to fetch sections: {e}"),
+    };
+
+    let mut batch = db.keyspace.batch();
+    for kv in db.sections.prefix(section_prefix(&site.id)) {
+        batch.remove(&db.sections, kv.unwrap().0);
+    }
+    for section in &sections {
+        batch.insert(
+            &db.sections,
+            section_key(&site.id, &section.id),
+            serde_json::to_vec(section).unwrap(),
+        );
+    }
+    batch.commit().unwrap();
+    info!("Stored {} sections", sections.len());
+}
+
+/// Fetch a single story, by url path or id
+#[instrument(skip(site), fields(site = site.id))]
+async fn req_story(site: &Site, story: StoryRef<'_>) -> Result<Value, Box<dyn Error>> {
+    let source = site.source.source();
+    let url = source
+        .story_url(site, story)
+        .ok_or_else(|| format!("{} can't fetch single stories", site.id))?;
+    let resp = get(&url).await?;
     info!("Status: {}", resp.status());
-    let text = resp.text().await?;
-    let json: serde_json::Value = serde_json::from_str(&text)?;
-    Ok(json)
+    let body = resp.bytes().await?;
+    source.parse_story(site, &body)
 }
 
+/// Download an object into the content-addressed store, returns its blob path.
+///
+/// The body is streamed to a temp file and renamed only after the response is
+/// checked to be complete and of the expected [`Kind`]; images must also be decodable.
+/// Not going through [`get`], it is written to the WARC files from its blob.
 #[instrument]
-async fn dl_obj(url: &str, path: &Path) -> Result<(), reqwest::Error> {
-    let resp = if !url.starts_with("http") {
-        error!("{url} is not valid.");
-        let new_url = format!("https://www.rfa.org/{url}");
-        CLIENT.get(new_url).send().await?
-    } else {
-        CLIENT.get(url).send().await?
-    };
+async fn dl_obj(url: &str, kind: Kind) -> Result<String, Box<dyn Error>> {
+    let mut resp = send(url).await?;
     info!("Status: {}", resp.status());
 
-    let bytes = resp.bytes().await?;
-    std::fs::write(path, &bytes).unwrap();
-    Ok(())
-}
+    let status = resp.status();
+    if !status.is_success() {
+        capture(url, resp).await?;
+        return Err(format!("unexpected status {status}").into());
+    }
+    let (version, headers) = (resp.version(), resp.headers().clone());
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    let ext = match (kind, mime) {
+        (Kind::Img, "image/jpeg") => "jpg",
+        (Kind::Img, "image/svg+xml") => "svg",
+        (Kind::Img, "image/x-icon" | "image/vnd.microsoft.icon") => "ico",
+        (Kind::Img, m) if m.starts_with("image/") => m.trim_start_matches("image/"),
+        (Kind::Media, "audio/mpeg") => "mp3",
+        (Kind::Media, "audio/mp4" | "audio/x-m4a") => "m4a",
+        (Kind::Media, m) if m.starts_with("audio/") || m.starts_with("video/") => {
+            m.split_once('/').unwrap().1
+        }
+        // CDNs often serve media as a plain binary stream
+        (Kind::Media, "application/octet-stream" | "binary/octet-stream") => url
+            .split('?')
+            .next()
+            .and_then(|u| u.rsplit_once('.'))
+            .map(|(_, ext)| ext)
+            .filter(|ext| !ext.contains('/'))
+            .unwrap_or("bin"),
+        _ => return Err(format!("unexpected content type {content_type:?}").into()),
+    };
+    let content_length = resp.content_length();
 
-fn extract(json: &Value) -> (Vec<String>, Vec<String>) {
-    let mut items = vec![];
-    let mut imgs = vec![];
-    if let Some(elements) = json["content_elements"].as_array() {
-        for item in elements {
-            let i = serde_json::to_string(&item).unwrap();
-            items.push(i);
+    static TMP_ID: AtomicU64 = AtomicU64::new(0);
+    let dir = Path::new(kind.dir());
+    let tmp_path = dir.join(format!(
+        ".{}.{}.part",
+        std::process::id(),
+        TMP_ID.fetch_add(1, Ordering::Relaxed)
+    ));
 
-            if let Some(promo_imgs) = item["promo_items"]["basic"]["url"].as_str() {
-                imgs.push(promo_imgs.to_owned())
+    let res = async {
+        let mut file = File::create(&tmp_path)?;
+        let mut hasher = blake3::Hasher::new();
+        let mut head = Vec::new();
+        let mut len = 0;
+        while let Some(chunk) = resp.chunk().await? {
+            THROTTLE.consume(chunk.len()).await;
+            hasher.update(&chunk);
+            file.write_all(&chunk)?;
+            if head.len() < HEAD_SIZE {
+                head.extend_from_slice(&chunk);
             }
+            len += chunk.len() as u64;
+        }
+        file.flush()?;
 
-            if let Some(contents) = item["content_elements"].as_array() {
-                for content in contents {
-                    if let Some(ctype) = content["type"].as_str()
-                        && ctype == "image"
-                    {
-                        if let Some(img_url) = content["content"].as_str() {
-                            imgs.push(img_url.to_owned());
-                        }
-                        if let Some(img_url) = content["url"].as_str() {
-                            imgs.push(img_url.to_owned());
-                        }
-                    }
-                }
-            }
+        if let Some(expected) = content_length
+            && len != expected
+        {
+            return Err(format!("truncated body, {len} of {expected} bytes").into());
+        }
+        // svg has no binary header to check
+        if kind == Kind::Img && ext != "svg" {
+            imagesize::blob_size(&head).map_err(|e| format!("undecodable image: {e}"))?;
+        }
+
+        let blob = blob_path(kind.dir(), &hasher.finalize(), ext);
+        let path = Path::new(&blob);
+        if !path.exists() {
+            create_dir_all(path.parent().unwrap())?;
+            std::fs::rename(&tmp_path, path)?;
+        }
+        // the blob is archived, a WARC failure must not fail its download
+        if let Some(warc) = &*WARC
+            && let Err(e) = File::open(path)
+                .and_then(|mut file| warc.write(url, status, version, &headers, &mut file))
+        {
+            error!("Failed to write the WARC record of {url}: {e}");
         }
+        Ok::<_, Box<dyn Error>>(blob)
     }
+    .await;
 
-    (items, imgs)
+    if tmp_path.exists() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Serialize the stories of a result page and collect their objects, skipping the `_id`s in `seen`
+fn extract(
+    source: &dyn Source,
+    stories: Vec<Value>,
+    seen: &mut HashSet<String>,
+) -> (Vec<String>, Objects) {
+    let mut items = vec![];
+    let mut objs = Objects::default();
+    for item in stories {
+        if let Some(id) = item["_id"].as_str()
+            && !seen.insert(id.to_owned())
+        {
+            continue;
+        }
+        items.push(serde_json::to_string(&item).unwrap());
+        objs.extend(source.objects(&item));
+    }
+    (items, objs)
 }
@@ -9,19 +9,29 @@ use axum::{
     response::{Html, IntoResponse, Redirect},
     routing::get,
 };
-use clap::Parser;
+use clap::{CommandFactory, Parser, error::ErrorKind};
 use fjall::{Config, PartitionCreateOptions, PartitionHandle};
 use include_dir::{Dir, include_dir};
 use jiff::{Timestamp, tz::TimeZone};
 use reqwest::StatusCode;
-use rfa::{get_filename_from_url, kv_sep_partition_option, site_code};
+use rfa::{
+    author::{Author, author_key, byline_prefix, byline_url},
+    blob_src,
+    compress::Codec,
+    kv_sep_partition_option, local_src, media_url, paragraphs, revision_key_ts, revision_prefix,
+    section::Section,
+    site::{Site, Sites},
+    version_ts,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use similar::{Algorithm, ChangeTag, capture_diff_slices};
 use tokio::net::TcpListener;
 use tower::Layer;
 use tower_http::{normalize_path::NormalizePathLayer, services::ServeDir};
 use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use urlencoding::encode;
 
 /// RFA backup website
 #[derive(Parser, Debug)]
@@ -30,12 +40,30 @@ struct Args {
     #[arg(short, long, default_value = "127.0.0.1:3333")]
     addr: String,
 
-    /// data folder, containing imgs/ and rfa.db/
+    /// data folder, containing imgs/, media/ and rfa.db/
     #[arg(short = 'd', long, default_value = "rfa_data")]
     data: String,
+
+    /// site registry, a JSON list of sites [default: <DATA>/sites.json if present, else the RFA services]
+    #[arg(long)]
+    sites_config: Option<PathBuf>,
 }
 
 static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
+static REGISTRY: LazyLock<Sites> = LazyLock::new(|| {
+    let path = match &ARGS.sites_config {
+        Some(path) => path.clone(),
+        None => PathBuf::from(&ARGS.data).join("sites.json"),
+    };
+    Sites::load(&path).unwrap_or_else(|e| {
+        Args::command()
+            .error(
+                ErrorKind::Io,
+                format!("site registry {}: {e}", path.display()),
+            )
+            .exit()
+    })
+});
 
 #[tokio::main]
 async fn main() {
@@ -44,6 +72,7 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    LazyLock::force(&REGISTRY);
     let folder = PathBuf::from(&ARGS.data);
     let db_folder = folder.join("rfa.db");
 
@@ -54,19 +83,51 @@ async fn main() {
     let index = keyspace
         .open_partition("index", PartitionCreateOptions::default())
         .unwrap();
-    let app_state = AppState { db, index };
+    let blobs = keyspace
+        .open_partition("blobs", PartitionCreateOptions::default())
+        .unwrap();
+    let revisions = keyspace
+        .open_partition("revisions", kv_sep_partition_option())
+        .unwrap();
+    let sections = keyspace
+        .open_partition("sections", PartitionCreateOptions::default())
+        .unwrap();
+    let authors = keyspace
+        .open_partition("authors", PartitionCreateOptions::default())
+        .unwrap();
+    let bylines = keyspace
+        .open_partition("bylines", PartitionCreateOptions::default())
+        .unwrap();
+    let dicts = keyspace
+        .open_partition("dicts", PartitionCreateOptions::default())
+        .unwrap();
+    let app_state = AppState {
+        db,
+        index,
+        blobs,
+        revisions,
+        sections,
+        authors,
+        bylines,
+        codec: Codec::new(dicts),
+    };
 
     let addr: SocketAddr = ARGS.addr.parse().unwrap();
     info!("Listening to {addr}");
 
     let img_folder = folder.join("imgs");
+    let media_folder = folder.join("media");
     let app = Router::new()
         .route("/", get(home))
         .route("/{site}", get(site))
+        .route("/{site}/sections", get(section_list))
+        .route("/{site}/author/{slug}", get(author))
         .route("/{site}/{*id}", get(page))
         .route("/style.css", get(style))
         .route("/static/imgs/{filename}", get(serve_imgs))
         .nest_service("/imgs", ServeDir::new(img_folder))
+        // ServeDir answers range requests, so players can seek
+        .nest_service("/media", ServeDir::new(media_folder))
         .with_state(app_state)
         .fallback(handler_404);
     let app = NormalizePathLayer::trim_trailing_slash().layer(app);
@@ -87,11 +148,36 @@ async fn page(
     let key = original_uri.split("?").next().unwrap().trim_matches('/');
     info!("page: {key}");
     if let Some(v) = state.db.get(key).unwrap() {
-        let content = String::from_utf8_lossy(&v);
-        let json: Value = serde_json::from_str(&content).unwrap();
-        let article: Article = (&json).into();
+        let Some(site) = site_of(key) else {
+            return outside_registry(key);
+        };
+        let json = state.codec.value(&v).unwrap();
+        if params.history.is_some() {
+            let revisions: Vec<(Option<Timestamp>, Value)> = state
+                .revisions
+                .prefix(revision_prefix(key))
+                .map(|kv| {
+                    let (k, v) = kv.unwrap();
+                    (revision_key_ts(&k), state.codec.value(&v).unwrap())
+                })
+                .collect();
+            let history = History::new(
+                json,
+                revisions,
+                &params,
+                site,
+                &state.blobs,
+                &state.sections,
+            );
+            return into_response(&history);
+        }
+        let mut article = Article::new(&json, site, &state.blobs, &state.sections);
+        // only the count is shown, the revisions are decoded for the history
+        article.revisions = state.revisions.prefix(revision_prefix(key)).count();
         into_response(&article)
-    } else if let Some((site, _)) = key.split_once('/') {
+    } else if let Some((site, _)) = key.split_once('/')
+        && let Some(site) = REGISTRY.by_prefix(site)
+    {
         let page = params.page.unwrap_or_default();
         let mut items = vec![];
         let n = page * 20;
@@ -103,8 +189,8 @@ async fn page(
                 break;
             }
             let (_, v) = i.unwrap();
-            let json: Value = serde_json::from_slice(&v).unwrap();
-            let item: Item = (&json).into();
+            let json = state.codec.value(&v).unwrap();
+            let item = Item::new(&json, site, &state.blobs, &state.sections);
             items.push(item);
         }
         if items.is_empty() {
@@ -112,11 +198,21 @@ async fn page(
             return (StatusCode::NOT_FOUND, "Not found").into_response();
         }
         let url_path = format!("/{key}");
+        let section = Section::load_all(&state.sections, &site.id)
+            .into_iter()
+            .find(|s| s.url == url_path);
+        let breadcrumbs = match &section {
+            Some(section) => crumbs(&state.sections, site, &section.id),
+            None => vec![],
+        };
         let page_list = PageList {
             items,
-            site: site.to_owned(),
+            site,
             page: page + 1,
             url_path,
+            section,
+            breadcrumbs,
+            author: None,
         };
         into_response(&page_list)
     } else {
@@ -125,13 +221,23 @@ async fn page(
     }
 }
 
+/// A story whose site was removed from the registry, there is no layout for it
+fn outside_registry(key: &str) -> Response<Body> {
+    error!("{key} is outside the site registry");
+    (StatusCode::NOT_FOUND, "Not found").into_response()
+}
+
 async fn home() -> impl IntoResponse {
-    Redirect::to("/english")
+    Redirect::to(&format!("/{}", REGISTRY.first().prefix))
 }
 
 #[derive(Deserialize)]
 struct SiteParams {
     page: Option<usize>,
+    /// show the revisions of an article, diffing version `from` against `to`
+    history: Option<String>,
+    from: Option<usize>,
+    to: Option<usize>,
 }
 
 #[derive(Debug, Serialize)]
@@ -140,66 +246,92 @@ enum ContentType {
     Image(String, String),
     Header(String),
     Link(String, String),
+    /// src, poster, caption
+    Video(String, Option<String>, String),
+    /// src, caption
+    Audio(String, String),
     #[allow(dead_code)]
     Other,
 }
 
+impl ContentType {
+    /// Player of an ANS `video` or `audio` element, if its file was archived
+    fn media(element: &Value, blobs: &PartitionHandle) -> Option<Self> {
+        let src = blob_src(blobs, media_url(element)?)?;
+        let caption = element["headlines"]["basic"]
+            .as_str()
+            .or_else(|| element["description"]["basic"].as_str())
+            .unwrap_or_default()
+            .to_owned();
+        match element["type"].as_str()? {
+            "video" => {
+                let poster = element["promo_image"]["url"]
+                    .as_str()
+                    .and_then(|url| blob_src(blobs, url));
+                Some(ContentType::Video(src, poster, caption))
+            }
+            "audio" => Some(ContentType::Audio(src, caption)),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Template, Debug, Serialize)]
 #[template(path = "article.html", escape = "none")]
 struct Article {
-    site: String,
+    site: &'static Site,
     item: Item,
-    author: Option<String>,
+    /// name and page of each credited author
+    authors: Vec<(String, String)>,
     contents: Vec<ContentType>,
+    /// number of superseded versions
+    revisions: usize,
+    breadcrumbs: Vec<(String, String)>,
 }
 
-impl From<&Value> for Article {
-    fn from(json: &Value) -> Self {
-        let item: Item = json.into();
-        let site = item
-            .website_url
-            .trim_start_matches('/')
-            .split_once('/')
-            .unwrap()
-            .0
-            .to_owned();
-        let author = json
-            .get("credits")
-            .and_then(|p| p.get("by"))
-            .and_then(|b| b.as_array())
-            .and_then(|a| a.first())
-            .and_then(|t| t.get("name"))
-            .and_then(|n| n.as_str())
-            .map(|s| s.to_owned());
+impl Article {
+    fn new(
+        json: &Value,
+        site: &'static Site,
+        blobs: &PartitionHandle,
+        sections: &PartitionHandle,
+    ) -> Self {
+        let item = Item::new(json, site, blobs, sections);
+        let breadcrumbs = crumbs(sections, site, &item.section_id);
+        let authors = Author::of_story(json, &site.id)
+            .into_iter()
+            .map(|a| {
+                let page = format!("/{}/author/{}", site.prefix, encode(&a.slug));
+                (a.name, page)
+            })
+            .collect();
 
         let mut contents = vec![];
+        let lead_art = &json["promo_items"]["lead_art"];
+        if let Some(media) = ContentType::media(lead_art, blobs) {
+            contents.push(media);
+        }
         if let Some(content_elements) = json["content_elements"].as_array() {
             for c in content_elements {
-                match c["type"].as_str().unwrap() {
-                    "text" => {
-                        let content = c["content"].as_str().unwrap();
+                // a malformed element is left out, not the whole article
+                match (c["type"].as_str(), c["content"].as_str(), c["url"].as_str()) {
+                    (Some("text"), Some(content), _) => {
                         if !content.is_empty() {
                             contents.push(ContentType::Text(content.to_owned()))
                         }
                     }
-                    "image" => {
-                        let url = c["url"].as_str().unwrap();
-                        let img_name = get_filename_from_url(url);
-                        let url = format!("/imgs/{img_name}");
+                    (Some("image"), _, Some(url)) => {
+                        let url = local_src(blobs, url);
                         let caption = c["caption"].as_str().unwrap_or_default();
                         contents.push(ContentType::Image(url, caption.to_owned()))
                     }
-                    "header" => {
-                        let content = c["content"].as_str().unwrap();
+                    (Some("header"), Some(content), _) => {
                         if !content.is_empty() {
                             contents.push(ContentType::Header(content.to_owned()))
                         }
                     }
-                    "interstitial_link" => {
-                        let url = c["url"]
-                            .as_str()
-                            .unwrap()
-                            .replace("https://www.rfa.org", "");
+                    (Some("interstitial_link"), _, Some(url)) => {
+                        let url = url.replace("https://www.rfa.org", "");
                         let content = if let Some(content) = c["content"].as_str() {
                             content.to_owned()
                         } else {
@@ -207,6 +339,16 @@ impl From<&Value> for Article {
                         };
                         contents.push(ContentType::Link(content, url));
                     }
+                    (Some("video" | "audio"), _, _) => {
+                        if let Some(media) = ContentType::media(c, blobs) {
+                            contents.push(media);
+                        } else {
+                            warn!("{} -> media not archived: {c}", item.website_url)
+                        }
+                    }
+                    (Some("text" | "image" | "header" | "interstitial_link"), _, _) => {
+                        warn!("{} -> malformed content element: {c}", item.website_url)
+                    }
                     _ => {
                         warn!("{} -> unknown content type: {c}", item.website_url)
                     }
@@ -217,8 +359,10 @@ impl From<&Value> for Article {
         Self {
             site,
             item,
-            author,
+            authors,
             contents,
+            revisions: 0,
+            breadcrumbs,
         }
     }
 }
@@ -231,11 +375,14 @@ async fn site(
     let index = state.index;
     let db = state.db;
     let mut items = Vec::with_capacity(20);
-    let code = site_code(&site);
+    let Some(site) = REGISTRY.by_prefix(&site) else {
+        error!("{} not found", site);
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
     let page = params.page.unwrap_or(0);
-    info!("site:{site} -> page:{page}");
+    info!("site:{} -> page:{page}", site.prefix);
     let n = page * 20;
-    for (idx, i) in index.prefix([code]).rev().enumerate() {
+    for (idx, i) in index.prefix([site.code]).rev().enumerate() {
         if idx < n {
             continue;
         }
@@ -244,29 +391,103 @@ async fn site(
         }
         let (k, _) = i.unwrap();
         let rest = String::from_utf8_lossy(&k[9..]);
-        let path = format!("{site}/{rest}");
+        let path = format!("{}/{rest}", site.prefix);
         if let Some(v) = db.get(&path).unwrap() {
-            let json: Value = serde_json::from_slice(&v).unwrap();
-            let item: Item = (&json).into();
+            let json = state.codec.value(&v).unwrap();
+            let item = Item::new(&json, site, &state.blobs, &state.sections);
             items.push(item)
         }
 
         if items.is_empty() {
-            error!("{} not found", site);
+            error!("{} not found", site.prefix);
             return (StatusCode::NOT_FOUND, "Not found").into_response();
         }
     }
 
-    let url_path = format!("/{}", site);
+    let url_path = format!("/{}", site.prefix);
     let page_list = PageList {
         items,
         site,
         page,
         url_path,
+        section: None,
+        breadcrumbs: vec![],
+        author: None,
     };
     into_response(&page_list)
 }
 
+/// Stories of an author, newest first
+async fn author(
+    Path((site, slug)): Path<(String, String)>,
+    Query(params): Query<SiteParams>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    // authors are kept per site, one of another site is not found here
+    let Some((site, author)) = REGISTRY.by_prefix(&site).and_then(|site| {
+        let author = Author::load(&state.authors, &site.id, &slug)?;
+        Some((site, author))
+    }) else {
+        error!("{site}/author/{slug} not found");
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    let page = params.page.unwrap_or(0);
+    let items = state
+        .bylines
+        .prefix(byline_prefix(&site.id, &slug))
+        .rev()
+        .skip(page * 20)
+        .take(20)
+        .filter_map(|kv| {
+            let v = state.db.get(byline_url(&kv.unwrap().0)?).unwrap()?;
+            let json = state.codec.value(&v).unwrap();
+            Some(Item::new(&json, site, &state.blobs, &state.sections))
+        })
+        .collect();
+    let photo = author
+        .photo
+        .as_deref()
+        .and_then(|url| blob_src(&state.blobs, url));
+    // the same slug on other sites, most likely the same person
+    let sites = REGISTRY
+        .iter()
+        .filter(|s| {
+            state
+                .authors
+                .contains_key(author_key(&s.id, &slug))
+                .unwrap()
+        })
+        .collect();
+
+    let page_list = PageList {
+        items,
+        site,
+        page,
+        url_path: format!("/{}/author/{}", site.prefix, encode(&slug)),
+        section: None,
+        breadcrumbs: vec![],
+        author: Some((author, photo, sites)),
+    };
+    into_response(&page_list)
+}
+
+/// Directory of the archived sections of a site
+async fn section_list(
+    Path(site): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(site) = REGISTRY.by_prefix(&site) else {
+        error!("{} not found", site);
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    // the stylesheet indents up to depth 5
+    let sections = Section::tree(Section::load_all(&state.sections, &site.id))
+        .into_iter()
+        .map(|(depth, section)| (depth.min(5), section))
+        .collect();
+    into_response(&SectionList { site, sections })
+}
+
 async fn handler_404(uri: Uri) -> impl IntoResponse {
     error!("No route for {}", uri);
     (
@@ -279,6 +500,25 @@ async fn handler_404(uri: Uri) -> impl IntoResponse {
 struct AppState {
     db: PartitionHandle,
     index: PartitionHandle,
+    blobs: PartitionHandle,
+    revisions: PartitionHandle,
+    sections: PartitionHandle,
+    authors: PartitionHandle,
+    bylines: PartitionHandle,
+    codec: Codec,
+}
+
+/// Site of a `website_url`, `None` for urls outside the registry
+fn site_of(website_url: &str) -> Option<&'static Site> {
+    REGISTRY.of_url(website_url)
+}
+
+/// Links to the section `id` and its ancestors, root first
+fn crumbs(sections: &PartitionHandle, site: &Site, id: &str) -> Vec<(String, String)> {
+    Section::breadcrumbs(sections, &site.id, id)
+        .into_iter()
+        .map(|s| (s.url, s.name))
+        .collect()
 }
 
 #[derive(Debug, Serialize)]
@@ -289,18 +529,26 @@ struct Item {
     promo_img: Option<String>,
     caption: Option<String>,
     website_url: String,
+    /// url of the story on its origin site
+    source_url: String,
+    /// `website_section._id`
+    section_id: String,
+    /// url and name of the section page
     section: (String, String),
 }
 
-impl From<&Value> for Item {
-    fn from(json: &Value) -> Self {
+impl Item {
+    /// Fields of a story of `site`, those missing are left empty
+    fn new(json: &Value, site: &Site, blobs: &PartitionHandle, sections: &PartitionHandle) -> Self {
         let headlines = json["headlines"]["basic"]
             .as_str()
             .unwrap_or_default()
             .to_owned();
-        let display_date = json["display_date"].as_str().unwrap_or_default();
-        let ts: Timestamp = display_date.parse().unwrap();
-        let display_date = ts.to_zoned(TimeZone::UTC).strftime("%Y-%m-%d").to_string();
+        let display_date = json["display_date"]
+            .as_str()
+            .and_then(|date| date.parse::<Timestamp>().ok())
+            .map(|ts| ts.to_zoned(TimeZone::UTC).strftime("%Y-%m-%d").to_string())
+            .unwrap_or_default();
 
         let description = json["description"]["basic"]
             .as_str()
@@ -311,10 +559,7 @@ impl From<&Value> for Item {
             .and_then(|p| p.get("basic"))
             .and_then(|b| b.get("url"))
             .and_then(|img| img.as_str())
-            .map(|s| {
-                let img_name = get_filename_from_url(s);
-                format!("/imgs/{img_name}")
-            });
+            .map(|s| local_src(blobs, s));
 
         let caption = json
             .get("promo_items")
@@ -323,27 +568,35 @@ impl From<&Value> for Item {
             .and_then(|c| c.as_str())
             .map(|s| s.to_owned());
 
-        let mut id = String::new();
-        let mut name = String::new();
-        let mut website_url = String::new();
-        if let Some(obj) = json["websites"].as_object()
-            && let Some((_, value)) = obj.iter().next()
-        {
-            website_url = value["website_url"].as_str().unwrap().to_owned();
-            let section = value.get("website_section").unwrap();
-            id = section
-                .get("_id")
-                .unwrap_or_default()
-                .as_str()
-                .unwrap_or_default()
-                .replace("world/asia/", "");
-            name = section
-                .get("name")
-                .unwrap_or_default()
-                .as_str()
-                .unwrap_or_default()
-                .to_owned();
-        }
+        // a story syndicated to several sites has an entry for each
+        let website = &json["websites"][&site.id];
+        let website_url = website["website_url"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        let section_id = website["website_section"]["_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        let name = website["website_section"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+
+        // the archived section page, else the folder of the story
+        let section = Section::load(sections, &site.id, &section_id);
+        let section = match section {
+            Some(section) => (section.url, section.name),
+            None => {
+                let folder = website_url.rsplit_once('/').map(|(folder, _)| folder);
+                (folder.unwrap_or_default().to_owned(), name)
+            }
+        };
+
+        let source_url = match json["canonical_url"].as_str() {
+            Some(url) => url.to_owned(),
+            None => format!("https://rfa.org{website_url}"),
+        };
 
         Item {
             headlines,
@@ -352,7 +605,80 @@ impl From<&Value> for Item {
             promo_img,
             caption,
             website_url,
-            section: (id, name),
+            source_url,
+            section_id,
+            section,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+enum Diff {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+#[derive(Template)]
+#[template(path = "history.html", escape = "none")]
+struct History {
+    site: &'static Site,
+    item: Item,
+    /// publish time of each version, oldest first, the last one is the current
+    versions: Vec<String>,
+    from: usize,
+    to: usize,
+    diff: Vec<Diff>,
+}
+
+impl History {
+    fn new(
+        current: Value,
+        revisions: Vec<(Option<Timestamp>, Value)>,
+        params: &SiteParams,
+        site: &'static Site,
+        blobs: &PartitionHandle,
+        sections: &PartitionHandle,
+    ) -> Self {
+        let item = Item::new(&current, site, blobs, sections);
+        let (mut times, mut revisions): (Vec<_>, Vec<_>) = revisions.into_iter().unzip();
+        times.push(version_ts(&current));
+        revisions.push(current);
+
+        let versions = times
+            .into_iter()
+            .map(|ts| {
+                ts.map(|ts| {
+                    ts.to_zoned(TimeZone::UTC)
+                        .strftime("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default()
+            })
+            .collect();
+
+        let last = revisions.len() - 1;
+        let to = params.to.unwrap_or(last).min(last);
+        let from = params.from.unwrap_or(to.saturating_sub(1)).min(last);
+        let old = paragraphs(&revisions[from]);
+        let new = paragraphs(&revisions[to]);
+        let diff = capture_diff_slices(Algorithm::Myers, &old, &new)
+            .iter()
+            .flat_map(|op| op.iter_changes(&old, &new))
+            .map(|change| match change.tag() {
+                ChangeTag::Equal => Diff::Same(change.value()),
+                ChangeTag::Insert => Diff::Added(change.value()),
+                ChangeTag::Delete => Diff::Removed(change.value()),
+            })
+            .collect();
+
+        Self {
+            site,
+            item,
+            versions,
+            from,
+            to,
+            diff,
         }
     }
 }
@@ -360,10 +686,24 @@ impl From<&Value> for Item {
 #[derive(Template)]
 #[template(path = "list.html")]
 struct PageList {
-    site: String,
+    site: &'static Site,
     items: Vec<Item>,
     page: usize,
     url_path: String,
+    /// the section listed, if `url_path` is an archived section page
+    section: Option<Section>,
+    breadcrumbs: Vec<(String, String)>,
+    /// the author listed, with the local src of their photo and the sites they
+    /// wrote for
+    author: Option<(Author, Option<String>, Vec<&'static Site>)>,
+}
+
+#[derive(Template)]
+#[template(path = "sections.html")]
+struct SectionList {
+    site: &'static Site,
+    /// in tree order, with their depth
+    sections: Vec<(usize, Section)>,
 }
 
 fn into_response<T: Template>(t: &T) -> Response<Body> {
@@ -387,19 +727,33 @@ async fn style() -> impl IntoResponse {
 
 static STATIC_LOGO_DIR: Dir = include_dir!("static/imgs");
 
+/// Bundled images, then the logos of extra sites in `<data>/logos/`
 async fn serve_imgs(Path(filename): Path<String>) -> impl IntoResponse {
-    if let Some(file) = STATIC_LOGO_DIR.get_file(&filename) {
-        let body = file.contents();
-
-        let mut headers = HeaderMap::new();
-        headers.insert("Content-Type", "image/png".parse().unwrap());
-        headers.insert(
-            HeaderName::from_static("cache-control"),
-            HeaderValue::from_static("public, max-age=1209600, s-maxage=86400"),
-        );
-
-        (headers, body).into_response()
+    let body = if let Some(file) = STATIC_LOGO_DIR.get_file(&filename) {
+        file.contents().to_vec()
+    } else if !filename.starts_with('.')
+        && !filename.contains(['/', '\\'])
+        && let Ok(body) =
+            tokio::fs::read(PathBuf::from(&ARGS.data).join("logos").join(&filename)).await
+    {
+        body
     } else {
-        (StatusCode::NOT_FOUND, "File not found").into_response()
-    }
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+
+    let content_type = match filename.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("svg") => "image/svg+xml",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        _ => "image/png",
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", content_type.parse().unwrap());
+    headers.insert(
+        HeaderName::from_static("cache-control"),
+        HeaderValue::from_static("public, max-age=1209600, s-maxage=86400"),
+    );
+
+    (headers, body).into_response()
 }
@@ -0,0 +1,140 @@
+//! zstd compression of the stories in the `rfa` and `revisions` partitions.
+//!
+//! Each site has its own dictionary, trained on its stories by `spider compress` and
+//! stored in the `dicts` partition under `<site id>\0<trained at>`, the last one being
+//! the current one. A frame names the dictionary it needs, older ones stay available.
+//! Values from before compression are plain JSON and read as is.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, Read},
+    sync::{Arc, RwLock},
+};
+
+use fjall::PartitionHandle;
+use jiff::Timestamp;
+use serde_json::Value;
+use zstd::{
+    dict::{DecoderDictionary, EncoderDictionary},
+    zstd_safe,
+};
+
+const LEVEL: i32 = 9;
+/// size of the trained dictionaries
+const DICT_SIZE: usize = 112 * 1024;
+/// magic number of a zstd frame
+const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone)]
+pub struct Codec {
+    dicts: PartitionHandle,
+    loaded: Arc<RwLock<Dicts>>,
+}
+
+#[derive(Default)]
+struct Dicts {
+    /// dictionary id -> dictionary
+    decoders: HashMap<u32, Arc<DecoderDictionary<'static>>>,
+    /// site id -> its current dictionary
+    encoders: HashMap<String, (u32, Arc<EncoderDictionary<'static>>)>,
+}
+
+impl Codec {
+    pub fn new(dicts: PartitionHandle) -> Self {
+        let codec = Codec {
+            dicts,
+            loaded: Default::default(),
+        };
+        codec.reload();
+        codec
+    }
+
+    /// Read the dictionaries again, e.g. after another process trained one
+    fn reload(&self) {
+        let mut loaded = Dicts::default();
+        for kv in self.dicts.iter() {
+            let (k, dict) = kv.unwrap();
+            let Some(id) = zstd_safe::get_dict_id_from_dict(&dict) else {
+                continue;
+            };
+            let site = k.split(|b| *b == 0).next().unwrap_or_default();
+            let site = String::from_utf8_lossy(site).into_owned();
+            loaded
+                .decoders
+                .insert(id.get(), Arc::new(DecoderDictionary::copy(&dict)));
+            // keys are sorted, the last one of a site wins
+            loaded.encoders.insert(
+                site,
+                (id.get(), Arc::new(EncoderDictionary::copy(&dict, LEVEL))),
+            );
+        }
+        *self.loaded.write().unwrap() = loaded;
+    }
+
+    /// Id of the current dictionary of a site, `None` until one is trained
+    pub fn dict_id(&self, site: &str) -> Option<u32> {
+        let loaded = self.loaded.read().unwrap();
+        loaded.encoders.get(site).map(|(id, _)| *id)
+    }
+
+    /// Compress a story of `site` with its dictionary, if any
+    pub fn encode(&self, site: &str, json: &[u8]) -> io::Result<Vec<u8>> {
+        let dict = self.loaded.read().unwrap().encoders.get(site).cloned();
+        match dict {
+            Some((_, dict)) => {
+                zstd::bulk::Compressor::with_prepared_dictionary(&dict)?.compress(json)
+            }
+            None => zstd::bulk::compress(json, LEVEL),
+        }
+    }
+
+    /// The JSON of a stored story, compressed or not
+    pub fn decode<'a>(&self, v: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        if !v.starts_with(&MAGIC) {
+            return Ok(Cow::Borrowed(v));
+        }
+        let Some(id) = zstd_safe::get_dict_id_from_frame(v) else {
+            return zstd::stream::decode_all(v).map(Cow::Owned);
+        };
+        let mut dict = self.loaded.read().unwrap().decoders.get(&id.get()).cloned();
+        if dict.is_none() {
+            self.reload();
+            dict = self.loaded.read().unwrap().decoders.get(&id.get()).cloned();
+        }
+        let Some(dict) = dict else {
+            return Err(io::Error::other(format!("unknown zstd dictionary {id}")));
+        };
+        let mut json = Vec::with_capacity(v.len() * 4);
+        zstd::stream::Decoder::with_prepared_dictionary(v, &dict)?.read_to_end(&mut json)?;
+        Ok(Cow::Owned(json))
+    }
+
+    /// A stored story, parsed
+    pub fn value(&self, v: &[u8]) -> io::Result<Value> {
+        Ok(serde_json::from_slice(&self.decode(v)?)?)
+    }
+
+    /// Train a dictionary on stories of a site, it becomes the current one. Returns
+    /// its id.
+    pub fn train(&self, site: &str, samples: &[Vec<u8>]) -> io::Result<u32> {
+        let dict = zstd::dict::from_samples(samples, DICT_SIZE)?;
+        let id = zstd_safe::get_dict_id_from_dict(&dict)
+            .ok_or_else(|| io::Error::other("trained dictionary without id"))?;
+        let mut key = site.as_bytes().to_vec();
+        key.push(0);
+        key.extend_from_slice(&Timestamp::now().as_millisecond().to_be_bytes());
+        self.dicts.insert(key, dict).map_err(io::Error::other)?;
+        self.reload();
+        Ok(id.get())
+    }
+}
+
+/// Id of the dictionary a stored story was compressed with, `None` for plain JSON
+/// and frames without one
+pub fn frame_dict_id(v: &[u8]) -> Option<u32> {
+    if !v.starts_with(&MAGIC) {
+        return None;
+    }
+    zstd_safe::get_dict_id_from_frame(v).map(|id| id.get())
+}
@@ -0,0 +1,109 @@
+//! Status of `spider daemon`, rewritten to a JSON file whenever a task starts or
+//! ends, so monitoring can read it without talking to the process.
+//!
+//! The daemon runs two tasks side by side, each on its own timer: a refresh of the
+//! last days, often, and a backfill of the months not done yet followed by
+//! `verify --repair`, seldom. A failing task is retried sooner, backing off
+//! exponentially up to its interval.
+
+use std::{io, path::Path, time::Duration};
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// first retry after a failure
+pub const RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started: String,
+    pub updated: String,
+    pub refresh: TaskStatus,
+    pub backfill: TaskStatus,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TaskStatus {
+    /// between two runs, in seconds
+    pub interval_secs: u64,
+    /// whether a run is going on, else waiting for the next one
+    pub running: bool,
+    pub runs: u64,
+    /// id of the last crawl run, see [`crate::report::RunReport`]
+    pub last_run: Option<i64>,
+    pub last_started: Option<String>,
+    pub last_finished: Option<String>,
+    /// end of the last run without failure
+    pub last_success: Option<String>,
+    pub last_error: Option<String>,
+    /// failed runs in a row
+    pub failures: u32,
+    pub next: Option<String>,
+    /// summary of the last run, e.g. the totals of the crawl
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub summary: serde_json::Value,
+}
+
+impl DaemonStatus {
+    /// Write to `path` through a temporary file, readers never see half of it
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        self.updated = Timestamp::now().to_string();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, path)
+    }
+
+    pub fn task(&mut self, backfill: bool) -> &mut TaskStatus {
+        if backfill {
+            &mut self.backfill
+        } else {
+            &mut self.refresh
+        }
+    }
+}
+
+impl TaskStatus {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval_secs: interval.as_secs(),
+            ..Default::default()
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.last_started = Some(Timestamp::now().to_string());
+        self.running = true;
+        self.runs += 1;
+    }
+
+    /// Record the end of a run, returns the delay until the next one
+    pub fn finish(&mut self, error: Option<String>) -> Duration {
+        let now = Timestamp::now();
+        self.last_finished = Some(now.to_string());
+        self.running = false;
+        let interval = Duration::from_secs(self.interval_secs);
+        let delay = match error {
+            Some(e) => {
+                self.failures += 1;
+                self.last_error = Some(e);
+                backoff(self.failures, interval)
+            }
+            None => {
+                self.failures = 0;
+                self.last_success = Some(now.to_string());
+                interval
+            }
+        };
+        self.next = now.checked_add(delay).ok().map(|next| next.to_string());
+        delay
+    }
+}
+
+/// Delay after `failures` failed runs in a row: [`RETRY`], doubled with every
+/// failure, `interval` at most
+pub fn backoff(failures: u32, interval: Duration) -> Duration {
+    RETRY
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(interval)
+}
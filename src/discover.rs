@@ -0,0 +1,131 @@
+//! Story urls listed by XML sitemaps and feeds, to catch the stories a listing by
+//! date window misses: back-dated ones, odd dates, or absent from the search index.
+
+use std::{error::Error, io::Read};
+
+use flate2::read::GzDecoder;
+use quick_xml::{Reader, events::Event};
+
+/// A story url, with its last modification or publication date if listed
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub url: String,
+    pub lastmod: Option<String>,
+}
+
+impl Link {
+    /// `YYYY-MM` of `lastmod`, to group gaps by month
+    pub fn month(&self) -> Option<String> {
+        let lastmod = self.lastmod.as_deref()?.trim();
+        let ts = lastmod.parse::<jiff::Timestamp>().ok().or_else(|| {
+            jiff::fmt::rfc2822::parse(lastmod)
+                .ok()
+                .map(|z| z.timestamp())
+        });
+        match ts {
+            Some(ts) => Some(ts.strftime("%Y-%m").to_string()),
+            // a plain date
+            None => lastmod
+                .get(..7)
+                .filter(|m| m.as_bytes().get(4) == Some(&b'-'))
+                .map(str::to_owned),
+        }
+    }
+}
+
+/// Urls of a sitemap or feed
+#[derive(Debug, Default)]
+pub struct Links {
+    /// nested sitemaps of a sitemap index
+    pub documents: Vec<String>,
+    pub stories: Vec<Link>,
+}
+
+/// Parse a sitemap index, a sitemap, an RSS or an Atom feed, gzipped or not
+pub fn parse(body: &[u8]) -> Result<Links, Box<dyn Error>> {
+    let mut unzipped = vec![];
+    let body = if body.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(body).read_to_end(&mut unzipped)?;
+        &unzipped[..]
+    } else {
+        body
+    };
+
+    let mut reader = Reader::from_reader(body);
+    let mut buf = vec![];
+    let mut links = Links::default();
+    // local names of the open elements
+    let mut stack: Vec<String> = vec![];
+    let mut text = String::new();
+    let mut link: Option<Link> = None;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                if matches!(name.as_str(), "url" | "item" | "entry") {
+                    link = Some(Link {
+                        url: String::new(),
+                        lastmod: None,
+                    });
+                }
+                stack.push(name);
+                text.clear();
+            }
+            Event::Empty(e) => {
+                // Atom: <link rel="alternate" href="…"/>
+                if e.local_name().as_ref() == b"link"
+                    && let Some(link) = link.as_mut()
+                    && link.url.is_empty()
+                    && let Some(href) = e.try_get_attribute("href")?
+                    && e.try_get_attribute("rel")?
+                        .is_none_or(|rel| rel.value.as_ref() == b"alternate")
+                {
+                    link.url = href.unescape_value()?.into_owned();
+                }
+            }
+            Event::Text(t) => text.push_str(&t.decode()?),
+            Event::GeneralRef(r) => {
+                text.push_str(&crate::source::unescape(&format!("&{};", r.decode()?)))
+            }
+            Event::CData(c) => text.push_str(&c.decode()?),
+            Event::End(_) => {
+                let name = stack.pop().unwrap_or_default();
+                let parent = stack.last().map(String::as_str).unwrap_or_default();
+                let value = text.trim();
+                match (parent, name.as_str()) {
+                    ("sitemap", "loc") => links.documents.push(value.to_owned()),
+                    ("url", "loc") | ("item", "link") | ("entry", "id")
+                        if value.starts_with("http") =>
+                    {
+                        if let Some(link) = link.as_mut()
+                            && (link.url.is_empty() || name != "id")
+                        {
+                            link.url = value.to_owned();
+                        }
+                    }
+                    ("url", "lastmod")
+                    | ("news", "publication_date")
+                    | ("item", "pubDate")
+                    | ("entry", "published") => {
+                        if let Some(link) = link.as_mut() {
+                            link.lastmod = Some(value.to_owned());
+                        }
+                    }
+                    (_, "url" | "item" | "entry") => {
+                        if let Some(link) = link.take()
+                            && !link.url.is_empty()
+                        {
+                            links.stories.push(link);
+                        }
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(links)
+}
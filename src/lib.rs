@@ -1,5 +1,19 @@
-use fjall::{KvSeparationOptions, PartitionCreateOptions};
+pub mod author;
+pub mod compress;
+pub mod daemon;
+pub mod discover;
+pub mod proxy;
+pub mod replay;
+pub mod report;
+pub mod section;
+pub mod site;
+pub mod source;
+pub mod throttle;
+pub mod warc;
+
+use fjall::{KvSeparationOptions, PartitionCreateOptions, PartitionHandle};
 use jiff::Timestamp;
+use serde_json::Value;
 
 pub fn kv_sep_partition_option() -> PartitionCreateOptions {
     PartitionCreateOptions::default()
@@ -11,28 +25,12 @@ pub fn kv_sep_partition_option() -> PartitionCreateOptions {
         )
 }
 
-pub fn site_code(website: &str) -> u8 {
-    match website.to_lowercase().as_ref() {
-        "english" => 0,
-        "mandarin" => 1,
-        "cantonese" => 2,
-        "burmese" => 3,
-        "korean" => 4,
-        "lao" => 5,
-        "khmer" => 6,
-        "tibetan" => 7,
-        "uyghur" => 8,
-        "vietnamese" => 9,
-        _ => 99,
-    }
-}
-
-/// site_code + ts + url_rest
-pub fn index_key(website_url: &str, display_date: &str) -> Vec<u8> {
-    let (website, rest) = website_url.trim_matches('/').split_once('/').unwrap();
-    let code = site_code(website);
+/// site_code + ts + url_rest, see [`site::Site::code`]. `None` for a `website_url`
+/// without a path after the site or a `display_date` that is no timestamp
+pub fn index_key(code: u8, website_url: &str, display_date: &str) -> Option<Vec<u8>> {
+    let (_, rest) = website_url.trim_matches('/').split_once('/')?;
 
-    let ts: Timestamp = display_date.parse().unwrap();
+    let ts: Timestamp = display_date.parse().ok()?;
     let ts_byte = ts.as_second().to_be_bytes();
 
     let rest_bytes = rest.as_bytes();
@@ -42,7 +40,7 @@ pub fn index_key(website_url: &str, display_date: &str) -> Vec<u8> {
     key.extend_from_slice(&ts_byte);
     key.extend_from_slice(rest.as_bytes());
 
-    key
+    Some(key)
 }
 
 pub fn get_filename_from_url(url: &str) -> &str {
@@ -51,3 +49,106 @@ pub fn get_filename_from_url(url: &str) -> &str {
         .and_then(|s| s.split('?').next())
         .unwrap()
 }
+
+/// Content-addressed path of a blob, relative to the data folder, e.g. `imgs/ab/ab12…ef.jpg`.
+///
+/// Identical files reused across stories or language services are stored once.
+pub fn blob_path(dir: &str, hash: &blake3::Hash, ext: &str) -> String {
+    let hash = hash.to_hex();
+    format!("{dir}/{}/{hash}.{ext}", &hash[..2])
+}
+
+/// Local url of an archived blob, `None` if it was never downloaded
+pub fn blob_src(blobs: &PartitionHandle, url: &str) -> Option<String> {
+    blobs
+        .get(url)
+        .unwrap()
+        .map(|path| format!("/{}", String::from_utf8_lossy(&path)))
+}
+
+/// Local url of a remote image, looked up in the `blobs` partition (url -> blob path).
+///
+/// Falls back to the legacy `imgs/<filename>` layout for images downloaded before
+/// the content-addressed store.
+pub fn local_src(blobs: &PartitionHandle, url: &str) -> String {
+    blob_src(blobs, url).unwrap_or_else(|| format!("/imgs/{}", get_filename_from_url(url)))
+}
+
+/// Best progressive stream of an ANS `video` or `audio` element.
+///
+/// Adaptive streams (hls, ts) can't be stored as a single file, so the progressive one
+/// with the highest bitrate wins.
+pub fn media_url(element: &Value) -> Option<&str> {
+    let best = element["streams"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|s| {
+            !matches!(
+                s["stream_type"].as_str().unwrap_or_default(),
+                "hls" | "ts" | "dash"
+            )
+        })
+        .filter(|s| s["url"].as_str().is_some_and(|u| !u.contains(".m3u8")))
+        .max_by_key(|s| s["bitrate"].as_u64().unwrap_or_default())
+        .and_then(|s| s["url"].as_str());
+    best.or_else(|| element["url"].as_str().filter(|u| !u.contains(".m3u8")))
+}
+
+/// url + `\0` + ts, so versions of a story sort by time and urls sharing a prefix don't mix
+pub fn revision_key(website_url: &str, ts: Timestamp) -> Vec<u8> {
+    let mut key = revision_prefix(website_url);
+    key.extend_from_slice(&ts.as_second().to_be_bytes());
+    key
+}
+
+pub fn revision_prefix(website_url: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(website_url.len() + 1 + 8);
+    key.extend_from_slice(website_url.as_bytes());
+    key.push(0);
+    key
+}
+
+/// Time a story version was published, `last_updated_date` with fallback to `display_date`
+pub fn version_ts(json: &Value) -> Option<Timestamp> {
+    json["last_updated_date"]
+        .as_str()
+        .or_else(|| json["display_date"].as_str())
+        .and_then(|ts| ts.parse().ok())
+}
+
+/// Time of a superseded version in its [`revision_key`]: `last_updated_date`, else
+/// now, when it gets replaced, as `display_date` stays the same across edits
+pub fn revision_ts(json: &Value) -> Timestamp {
+    json["last_updated_date"]
+        .as_str()
+        .and_then(|ts| ts.parse().ok())
+        .unwrap_or_else(Timestamp::now)
+}
+
+/// Time of a `revisions` key, see [`revision_key`]
+pub fn revision_key_ts(key: &[u8]) -> Option<Timestamp> {
+    let ts = key.get(key.len().checked_sub(8)?..)?;
+    Timestamp::from_second(i64::from_be_bytes(ts.try_into().ok()?)).ok()
+}
+
+/// Readable text of a story, one entry per paragraph, used to tell and show revisions apart
+pub fn paragraphs(json: &Value) -> Vec<String> {
+    let mut paragraphs = vec![];
+    for p in [&json["headlines"]["basic"], &json["description"]["basic"]] {
+        if let Some(p) = p.as_str() {
+            paragraphs.push(p.to_owned());
+        }
+    }
+    if let Some(content_elements) = json["content_elements"].as_array() {
+        for c in content_elements {
+            if matches!(c["type"].as_str(), Some("text" | "header"))
+                && let Some(content) = c["content"].as_str()
+                && !content.is_empty()
+            {
+                paragraphs.push(content.to_owned());
+            }
+        }
+    }
+    paragraphs
+}
@@ -0,0 +1,225 @@
+//! A pool of proxies the spider rotates through, per request.
+//!
+//! HTTP(S) and SOCKS5 proxies are supported, `socks5h://` resolving host names on
+//! the proxy as Tor wants. A connection failure, a gateway error or a 429 puts the
+//! proxy on cooldown, doubled with every failure in a row, and the request moves on
+//! to the next proxy.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::future;
+use reqwest::{Client, ClientBuilder, Proxy, Response, StatusCode};
+use tokio::time::{Instant, sleep_until};
+use tracing::{info, warn};
+
+use crate::report::ProxyStats;
+
+const COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+
+pub struct ProxyPool {
+    proxies: Vec<PoolProxy>,
+    next: AtomicUsize,
+}
+
+struct PoolProxy {
+    /// without credentials, for logs and stats
+    label: String,
+    client: Client,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    stats: ProxyStats,
+    failures_in_row: u32,
+    cooldown_until: Option<Instant>,
+}
+
+impl ProxyPool {
+    /// A client per proxy, each built from `builder`
+    pub fn new(
+        urls: &[String],
+        builder: impl Fn() -> ClientBuilder,
+    ) -> Result<Self, reqwest::Error> {
+        let proxies = urls
+            .iter()
+            .map(|url| {
+                let client = builder().proxy(Proxy::all(url)?).build()?;
+                let label = redact(url);
+                let state = State {
+                    stats: ProxyStats {
+                        proxy: label.clone(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                Ok(PoolProxy {
+                    label,
+                    client,
+                    state: Mutex::new(state),
+                })
+            })
+            .collect::<Result<_, reqwest::Error>>()?;
+        Ok(Self {
+            proxies,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.proxies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    /// Request `url` through every proxy, the failing ones start on cooldown
+    pub async fn check(&self, url: &str) {
+        let checks = self.proxies.iter().map(|p| async move {
+            let res = p.client.get(url).send().await;
+            let ok = matches!(&res, Ok(resp) if !is_proxy_failure(resp.status()));
+            let mut state = p.state.lock().unwrap();
+            state.stats.checks += 1;
+            if ok {
+                info!("Proxy {} is up", p.label);
+            } else {
+                match res {
+                    Ok(resp) => warn!("Proxy {} is down: status {}", p.label, resp.status()),
+                    Err(e) => warn!("Proxy {} is down: {e}", p.label),
+                }
+                state.stats.failed_checks += 1;
+                fail(&mut state);
+            }
+        });
+        future::join_all(checks).await;
+    }
+
+    /// GET `url` through the next proxy in turn, moving on to the next one on
+    /// failure, up to once per proxy. `before_each` runs before every attempt.
+    pub async fn get(
+        &self,
+        url: &str,
+        before_each: impl AsyncFn(),
+    ) -> Result<Response, reqwest::Error> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let last = attempt >= self.proxies.len();
+            let p = self.pick().await;
+            before_each().await;
+            let res = p.client.get(url).send().await;
+            let mut state = p.state.lock().unwrap();
+            state.stats.requests += 1;
+            match res {
+                Ok(resp) if is_proxy_failure(resp.status()) => {
+                    let status = resp.status();
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        state.stats.rate_limited += 1;
+                    } else {
+                        state.stats.failures += 1;
+                    }
+                    fail(&mut state);
+                    warn!("Proxy {} answered {status} for {url}", p.label);
+                    if last {
+                        return Ok(resp);
+                    }
+                }
+                Ok(resp) => {
+                    state.failures_in_row = 0;
+                    state.cooldown_until = None;
+                    return Ok(resp);
+                }
+                Err(e) if e.is_connect() || e.is_timeout() => {
+                    state.stats.failures += 1;
+                    fail(&mut state);
+                    warn!("Proxy {} failed for {url}: {e}", p.label);
+                    if last {
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The next proxy off cooldown, waiting for the first one back if all are on it
+    async fn pick(&self) -> &PoolProxy {
+        let n = self.proxies.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut soonest: Option<(Instant, &PoolProxy)> = None;
+        for i in 0..n {
+            let p = &self.proxies[(start + i) % n];
+            match p.state.lock().unwrap().cooldown_until {
+                Some(until) if until > now => {
+                    if soonest.is_none_or(|(s, _)| until < s) {
+                        soonest = Some((until, p));
+                    }
+                }
+                _ => return p,
+            }
+        }
+        let (until, p) = soonest.unwrap();
+        warn!(
+            "All proxies on cooldown, waiting {}s for {}",
+            (until - now).as_secs(),
+            p.label
+        );
+        sleep_until(until).await;
+        p
+    }
+
+    /// Statistics of every proxy so far
+    pub fn stats(&self) -> Vec<ProxyStats> {
+        let now = Instant::now();
+        self.proxies
+            .iter()
+            .map(|p| {
+                let state = p.state.lock().unwrap();
+                ProxyStats {
+                    healthy: state.cooldown_until.is_none_or(|until| until <= now),
+                    ..state.stats.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Statuses meaning the proxy, not the origin, failed or got rate-limited
+fn is_proxy_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::PROXY_AUTHENTICATION_REQUIRED
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn fail(state: &mut State) {
+    state.failures_in_row += 1;
+    let cooldown = COOLDOWN
+        .saturating_mul(1 << (state.failures_in_row - 1).min(10))
+        .min(MAX_COOLDOWN);
+    state.cooldown_until = Some(Instant::now() + cooldown);
+}
+
+/// `url` without its user and password
+fn redact(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => match rest.rsplit_once('@') {
+            Some((_, host)) => format!("{scheme}://***@{host}"),
+            None => url.to_owned(),
+        },
+        None => url.to_owned(),
+    }
+}
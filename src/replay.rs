@@ -0,0 +1,126 @@
+//! Recorded HTTP responses, so the spider runs end-to-end without network.
+//!
+//! `spider --record <DIR>` saves every response as a fixture, `<hash>.json` with
+//! the url, status and headers and `<hash>.body`, the hash being the blake3 of the
+//! url. [`serve`] replays them: `spider --replay <ADDR>` sends its requests to
+//! `http://<ADDR>/?url=<url>` instead of the origin.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{Query, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+use urlencoding::encode;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+/// Headers describing the transfer rather than the body, reqwest already decoded it
+const SKIPPED_HEADERS: [&str; 4] = [
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "connection",
+];
+
+/// `<dir>/<hash>`, the fixture files of `url` without extension
+fn stem(dir: &Path, url: &str) -> PathBuf {
+    dir.join(blake3::hash(url.as_bytes()).to_hex().as_str())
+}
+
+/// Save a response of `url` into `dir`
+pub fn save(
+    dir: &Path,
+    url: &str,
+    status: u16,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> io::Result<()> {
+    let headers = headers
+        .iter()
+        .filter(|(k, _)| !SKIPPED_HEADERS.contains(&k.as_str()))
+        .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_owned())))
+        .collect();
+    let fixture = Fixture {
+        url: url.to_owned(),
+        status,
+        headers,
+    };
+    let stem = stem(dir, url);
+    std::fs::write(stem.with_extension("body"), body)?;
+    std::fs::write(
+        stem.with_extension("json"),
+        serde_json::to_vec_pretty(&fixture)?,
+    )
+}
+
+/// The recorded response of `url`, `None` if there is none
+pub fn load(dir: &Path, url: &str) -> io::Result<Option<(Fixture, Vec<u8>)>> {
+    let stem = stem(dir, url);
+    let meta = match std::fs::read(stem.with_extension("json")) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let fixture = serde_json::from_slice(&meta)?;
+    let body = std::fs::read(stem.with_extension("body"))?;
+    Ok(Some((fixture, body)))
+}
+
+/// Url of `url` on the replay server at `addr`
+pub fn replay_url(addr: &str, url: &str) -> String {
+    format!("http://{addr}/?url={}", encode(url))
+}
+
+/// Replay the fixtures of `dir`, answering 404 to requests without one
+pub async fn serve(dir: PathBuf, listener: TcpListener) -> io::Result<()> {
+    info!("Replaying {} on {}", dir.display(), listener.local_addr()?);
+    let app = Router::new().route("/", get(replay)).with_state(dir);
+    axum::serve(listener, app).await
+}
+
+async fn replay(
+    State(dir): State<PathBuf>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(url) = params.get("url") else {
+        return (StatusCode::BAD_REQUEST, "missing url").into_response();
+    };
+    let (fixture, body) = match load(&dir, url) {
+        Ok(Some(recorded)) => recorded,
+        Ok(None) => {
+            warn!("No fixture for {url}");
+            return (StatusCode::NOT_FOUND, "no fixture").into_response();
+        }
+        Err(e) => {
+            warn!("Broken fixture for {url}: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = StatusCode::from_u16(fixture.status).unwrap_or(StatusCode::OK);
+    for (k, v) in fixture.headers {
+        if let (Ok(k), Ok(v)) = (HeaderName::try_from(k), HeaderValue::try_from(v)) {
+            resp.headers_mut().append(k, v);
+        }
+    }
+    resp
+}
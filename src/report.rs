@@ -0,0 +1,171 @@
+//! Structured reports of crawl runs, persisted in the `runs` partition.
+//!
+//! A run is stored under its 8-byte id, each crawled window under the run id
+//! followed by the site and the window start, so a prefix scan on the id returns
+//! the run then its windows.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use fjall::PartitionHandle;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// Downloads of one kind of object
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ObjStats {
+    pub downloaded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+impl ObjStats {
+    pub fn add(&mut self, other: &ObjStats) {
+        self.downloaded += other.downloaded;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+    }
+}
+
+/// Requests through one proxy of the pool, see [`crate::proxy::ProxyPool`]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProxyStats {
+    /// proxy url, without credentials
+    pub proxy: String,
+    pub requests: u64,
+    /// connection failures and gateway errors
+    pub failures: u64,
+    /// 429 answers
+    pub rate_limited: u64,
+    pub checks: u64,
+    pub failed_checks: u64,
+    /// off cooldown at the end of the run
+    pub healthy: bool,
+}
+
+/// One site and date window, usually a month
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct WindowReport {
+    pub site: String,
+    pub begin: String,
+    pub end: String,
+    /// `count` reported by the API
+    pub count: u64,
+    /// stories received
+    pub fetched: u64,
+    /// new or changed stories written
+    pub stored: u64,
+    pub imgs: ObjStats,
+    pub media: ObjStats,
+    pub duration_ms: u64,
+    /// whether the window was crawled to the end
+    pub complete: bool,
+    pub errors: Vec<String>,
+}
+
+impl WindowReport {
+    pub fn new(site: &str, begin: impl ToString, end: impl ToString) -> Self {
+        Self {
+            site: site.to_owned(),
+            begin: begin.to_string(),
+            end: end.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    /// start time in microseconds
+    pub id: i64,
+    /// `crawl`, `refresh`, …
+    pub mode: String,
+    pub sites: Vec<String>,
+    pub started: String,
+    pub finished: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proxies: Vec<ProxyStats>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<WindowReport>,
+}
+
+impl RunReport {
+    pub fn new(mode: &str, sites: &[String]) -> Self {
+        // the daemon starts a refresh and a backfill together, ids must differ
+        static LAST_ID: AtomicI64 = AtomicI64::new(0);
+        let now = Timestamp::now();
+        let id = now.as_microsecond();
+        let last = LAST_ID
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(id.max(last + 1))
+            })
+            .unwrap();
+        Self {
+            id: id.max(last + 1),
+            mode: mode.to_owned(),
+            sites: sites.to_vec(),
+            started: now.to_string(),
+            finished: None,
+            proxies: vec![],
+            windows: vec![],
+        }
+    }
+
+    fn key(&self) -> [u8; 8] {
+        self.id.to_be_bytes()
+    }
+
+    /// Save the run itself, without its windows
+    pub fn save(&self, runs: &PartitionHandle) {
+        let summary = RunReport {
+            windows: vec![],
+            ..self.clone()
+        };
+        runs.insert(self.key(), serde_json::to_vec(&summary).unwrap())
+            .unwrap();
+    }
+
+    pub fn finish(&mut self, runs: &PartitionHandle) {
+        self.finished = Some(Timestamp::now().to_string());
+        self.save(runs);
+    }
+
+    pub fn save_window(&self, runs: &PartitionHandle, window: &WindowReport) {
+        let mut key = self.key().to_vec();
+        key.extend_from_slice(window.site.as_bytes());
+        key.push(0);
+        key.extend_from_slice(window.begin.as_bytes());
+        runs.insert(key, serde_json::to_vec(window).unwrap())
+            .unwrap();
+    }
+
+    /// All runs with their windows, oldest first
+    pub fn load_all(runs: &PartitionHandle) -> Vec<RunReport> {
+        let mut reports: Vec<RunReport> = vec![];
+        for kv in runs.iter() {
+            let (k, v) = kv.unwrap();
+            if k.len() == 8 {
+                reports.push(serde_json::from_slice(&v).unwrap());
+            } else if let Some(run) = reports.last_mut() {
+                run.windows.push(serde_json::from_slice(&v).unwrap());
+            }
+        }
+        reports
+    }
+
+    /// Totals over all windows
+    pub fn totals(&self) -> WindowReport {
+        let mut total = WindowReport::new("*", "", "");
+        total.complete = true;
+        for w in &self.windows {
+            total.count += w.count;
+            total.fetched += w.fetched;
+            total.stored += w.stored;
+            total.imgs.add(&w.imgs);
+            total.media.add(&w.media);
+            total.duration_ms += w.duration_ms;
+            total.complete &= w.complete;
+            total.errors.extend(w.errors.iter().cloned());
+        }
+        total
+    }
+}
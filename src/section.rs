@@ -0,0 +1,96 @@
+//! Section taxonomy of the sites, stored in the `sections` partition under
+//! `<site id>\0<section id>`, so a prefix scan on the site id returns its tree.
+
+use fjall::PartitionHandle;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    /// as in `website_section._id` of the stories, e.g. `/korean/news`
+    pub id: String,
+    pub name: String,
+    /// id of the parent, `None` for the root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// path of the section page, the url prefix of its stories
+    pub url: String,
+    /// position among its siblings
+    #[serde(default)]
+    pub order: u32,
+}
+
+pub fn section_key(site: &str, id: &str) -> Vec<u8> {
+    let mut key = section_prefix(site);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+pub fn section_prefix(site: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(site.len() + 1);
+    key.extend_from_slice(site.as_bytes());
+    key.push(0);
+    key
+}
+
+impl Section {
+    pub fn load(sections: &PartitionHandle, site: &str, id: &str) -> Option<Section> {
+        let v = sections.get(section_key(site, id)).unwrap()?;
+        serde_json::from_slice(&v).ok()
+    }
+
+    /// All sections of a site
+    pub fn load_all(sections: &PartitionHandle, site: &str) -> Vec<Section> {
+        sections
+            .prefix(section_prefix(site))
+            .filter_map(|kv| serde_json::from_slice(&kv.unwrap().1).ok())
+            .collect()
+    }
+
+    /// The section `id` and its ancestors, root first
+    pub fn breadcrumbs(sections: &PartitionHandle, site: &str, id: &str) -> Vec<Section> {
+        let mut crumbs: Vec<Section> = vec![];
+        let mut next = Some(id.to_owned());
+        while let Some(id) = next {
+            // a broken tree could loop
+            if crumbs.len() >= 16 || crumbs.iter().any(|s| s.id == id) {
+                break;
+            }
+            let Some(section) = Section::load(sections, site, &id) else {
+                break;
+            };
+            next = section.parent.clone();
+            crumbs.push(section);
+        }
+        crumbs.reverse();
+        crumbs
+    }
+
+    /// Sections of a site in tree order, each with its depth
+    pub fn tree(mut sections: Vec<Section>) -> Vec<(usize, Section)> {
+        sections.sort_by(|a, b| a.order.cmp(&b.order).then_with(|| a.name.cmp(&b.name)));
+        let ids: Vec<String> = sections.iter().map(|s| s.id.clone()).collect();
+        let mut tree = Vec::with_capacity(sections.len());
+        let mut stack: Vec<(usize, Section)> = sections
+            .iter()
+            .filter(|s| s.parent.as_ref().is_none_or(|p| !ids.contains(p)))
+            .rev()
+            .map(|s| (0, s.clone()))
+            .collect();
+        while let Some((depth, section)) = stack.pop() {
+            if tree.len() >= sections.len() {
+                break;
+            }
+            stack.extend(
+                sections
+                    .iter()
+                    .filter(|s| s.parent.as_ref() == Some(&section.id))
+                    .rev()
+                    .map(|s| (depth + 1, s.clone())),
+            );
+            tree.push((depth, section));
+        }
+        tree
+    }
+}
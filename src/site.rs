@@ -0,0 +1,141 @@
+//! Registry of the archived language services, shared by the spider and the web.
+//!
+//! The built-in registry holds the RFA services. A JSON file with a list of
+//! [`Site`]s replaces it, so a new service needs no code changes, and every
+//! site of the registry gets its entry in the navigation of the web:
+//!
+//! ```json
+//! [{"id": "benarnews-en", "prefix": "benarnews", "code": 10, "lang": "en", "name": "BenarNews", "logo": "logo-benarnews.png"}]
+//! ```
+
+use std::{collections::HashSet, error::Error, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::source::Backend;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Site {
+    /// Arc website id, e.g. `rfa-mandarin`
+    pub id: String,
+    /// first segment of `website_url`, e.g. `mandarin`
+    pub prefix: String,
+    /// first byte of the index keys, see [`crate::index_key`]
+    pub code: u8,
+    /// BCP-47 language tag
+    pub lang: String,
+    /// text direction, `ltr` or `rtl`
+    #[serde(default = "ltr")]
+    pub dir: String,
+    /// native display name
+    pub name: String,
+    /// logo file, bundled in `static/imgs/` or put in `<data>/logos/`
+    pub logo: String,
+    /// where the stories come from, Arc on www.rfa.org by default
+    #[serde(default)]
+    pub source: Backend,
+    /// sitemaps and section feeds listing stories, those of the source if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub discovery: Vec<String>,
+}
+
+impl Site {
+    /// Sitemaps and feeds to discover stories from, see [`crate::discover`]
+    pub fn discovery_urls(&self) -> Vec<String> {
+        if self.discovery.is_empty() {
+            self.source.source().discovery(self)
+        } else {
+            self.discovery.clone()
+        }
+    }
+}
+
+fn ltr() -> String {
+    "ltr".to_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Sites(Vec<Site>);
+
+impl Sites {
+    /// Load the registry from `path` if it exists, otherwise the built-in one
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Self::builtin());
+        }
+        let sites: Vec<Site> = serde_json::from_slice(&std::fs::read(path)?)?;
+        Self::new(sites)
+    }
+
+    pub fn new(sites: Vec<Site>) -> Result<Self, Box<dyn Error>> {
+        if sites.is_empty() {
+            return Err("empty site registry".into());
+        }
+        let mut ids = HashSet::new();
+        let mut prefixes = HashSet::new();
+        let mut codes = HashSet::new();
+        for site in &sites {
+            if !ids.insert(&site.id) || !prefixes.insert(&site.prefix) || !codes.insert(site.code) {
+                return Err(format!("duplicate id, prefix or code of site {}", site.id).into());
+            }
+        }
+        Ok(Self(sites))
+    }
+
+    pub fn builtin() -> Self {
+        let site = |id: &str, prefix: &str, code, lang: &str, dir: &str, name: &str| Site {
+            id: id.to_owned(),
+            prefix: prefix.to_owned(),
+            code,
+            lang: lang.to_owned(),
+            dir: dir.to_owned(),
+            name: name.to_owned(),
+            logo: format!("logo-{prefix}.png"),
+            source: Backend::default(),
+            discovery: vec![],
+        };
+        Self(vec![
+            site("radio-free-asia", "english", 0, "en", "ltr", "English"),
+            site("rfa-mandarin", "mandarin", 1, "zh-Hans", "ltr", "普通话"),
+            site("rfa-cantonese", "cantonese", 2, "yue", "ltr", "粤语"),
+            site("rfa-burmese", "burmese", 3, "my", "ltr", "မြန်မာ"),
+            site("rfa-korean", "korean", 4, "ko", "ltr", "한국어"),
+            site("rfa-lao", "lao", 5, "lo", "ltr", "ລາວ"),
+            site("rfa-khmer", "khmer", 6, "km", "ltr", "ខ្មែរ"),
+            site("rfa-tibetan", "tibetan", 7, "bo", "ltr", "བོད་ཡིག"),
+            site("rfa-uyghur", "uyghur", 8, "ug", "rtl", "ئۇيغۇر"),
+            site("rfa-vietnamese", "vietnamese", 9, "vi", "ltr", "Tiếng Việt"),
+        ])
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Site> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Site> {
+        self.0.iter_mut()
+    }
+
+    pub fn first(&self) -> &Site {
+        &self.0[0]
+    }
+
+    pub fn by_id(&self, id: &str) -> Option<&Site> {
+        self.0.iter().find(|s| s.id == id)
+    }
+
+    pub fn by_prefix(&self, prefix: &str) -> Option<&Site> {
+        let prefix = prefix.to_lowercase();
+        self.0.iter().find(|s| s.prefix == prefix)
+    }
+
+    pub fn by_code(&self, code: u8) -> Option<&Site> {
+        self.0.iter().find(|s| s.code == code)
+    }
+
+    /// Site of a `website_url`, e.g. `/mandarin/news/…`
+    pub fn of_url(&self, website_url: &str) -> Option<&Site> {
+        let prefix = website_url.trim_start_matches('/').split('/').next()?;
+        self.by_prefix(prefix)
+    }
+}
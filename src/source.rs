@@ -0,0 +1,557 @@
+//! Content sources the spider crawls, one backend per CMS.
+//!
+//! A [`Source`] only builds request urls and parses responses, the spider keeps
+//! the HTTP client, throttling and storage. Whatever the backend, stories come
+//! out as ANS JSON, the form of the Arc API that `rfa` stores and the web reads:
+//!
+//! - `_id`, `display_date`, `last_updated_date`, `canonical_url`
+//! - `headlines.basic`, `description.basic`
+//! - `credits.by[]`: `name`, and `slug`, `url`, `description`, `image.url` if known
+//! - `websites.<site id>.website_url`, starting with the site prefix, and `website_section`
+//! - `promo_items.basic` (`url`, `caption`) and `promo_items.lead_art` for a video or audio
+//! - `content_elements`: `text`, `header`, `image`, `video` and `audio` elements
+
+mod arc;
+mod rss;
+mod wordpress;
+
+use std::error::Error;
+
+use jiff::{Timestamp, civil::Date};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use urlencoding::encode;
+
+pub use arc::{ArcXp, StoryFilter};
+pub use rss::Rss;
+pub use wordpress::WordPress;
+
+use crate::{media_url, section::Section, site::Site};
+
+/// A page of stories to list
+#[derive(Debug, Clone, Copy)]
+pub struct Query {
+    /// first day, inclusive
+    pub begin: Date,
+    /// last day, inclusive
+    pub end: Date,
+    /// stories to skip
+    pub offset: u64,
+    /// page size
+    pub size: u64,
+}
+
+/// Stories of a listing response
+#[derive(Debug, Default)]
+pub struct Listing {
+    /// stories in the whole window, if the backend tells
+    pub count: Option<u64>,
+    /// offset of the next page, `None` on the last page
+    pub next: Option<u64>,
+    /// stories, normalised to ANS
+    pub stories: Vec<Value>,
+}
+
+/// A single story, as given on the command line
+#[derive(Debug, Clone, Copy)]
+pub enum StoryRef<'a> {
+    /// path of the story url, e.g. `/mandarin/news/…`
+    Path(&'a str),
+    /// id in the backend, `_id` for Arc
+    Id(&'a str),
+}
+
+/// Images and media files of a story, media by url
+#[derive(Debug, Default)]
+pub struct Objects {
+    pub imgs: Vec<Image>,
+    pub media: Vec<String>,
+}
+
+/// An image to archive
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// as in the story, the key of the `blobs` partition
+    pub url: String,
+    /// where its original resolution is downloaded from
+    pub src: String,
+    /// size of the original, if the story tells
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub credit: Option<String>,
+}
+
+impl Image {
+    /// An image known by its url only, e.g. a video poster
+    pub fn plain(url: &str, host: &str) -> Self {
+        Image {
+            url: url.to_owned(),
+            src: original_src(url, host),
+            width: None,
+            height: None,
+            credit: None,
+        }
+    }
+
+    /// An ANS `image`, from the Arc resizer when it carries a resizer token
+    pub fn from_ans(element: &Value, host: &str) -> Option<Self> {
+        let url = element["url"].as_str()?;
+        let mut image = Image::plain(url, host);
+        if let (Some(id), Some(token)) = (element["_id"].as_str(), element["auth"]["1"].as_str()) {
+            // the resizer serves the original when asked for no size
+            let path = url.split(['?', '#']).next().unwrap_or_default();
+            let ext = path
+                .rsplit_once('.')
+                .map(|(_, ext)| ext)
+                .filter(|ext| !ext.contains('/'))
+                .unwrap_or("jpg");
+            image.src = format!(
+                "https://{host}/resizer/v2/{}.{ext}?auth={}",
+                encode(id),
+                encode(token)
+            );
+        }
+        image.width = element["width"].as_u64();
+        image.height = element["height"].as_u64();
+        let credits: Vec<&str> = ["by", "affiliation"]
+            .iter()
+            .flat_map(|k| element["credits"][k].as_array().into_iter().flatten())
+            .filter_map(|c| c["name"].as_str().filter(|n| !n.is_empty()))
+            .collect();
+        if !credits.is_empty() {
+            image.credit = Some(credits.join(", "));
+        }
+        Some(image)
+    }
+}
+
+/// Absolute url of the original of an image: relative urls are on `host`, and
+/// resizer urls lose their size parameters
+fn original_src(url: &str, host: &str) -> String {
+    let url = if url.starts_with("http") {
+        url.to_owned()
+    } else {
+        format!("https://{host}/{}", url.trim_start_matches('/'))
+    };
+    let Some((path, query)) = url
+        .split_once('?')
+        .filter(|(path, _)| path.contains("/resizer/"))
+    else {
+        return url;
+    };
+    match query.split('&').find(|param| param.starts_with("auth=")) {
+        Some(auth) => format!("{path}?{auth}"),
+        None => path.to_owned(),
+    }
+}
+
+impl Objects {
+    /// Collect the objects of an ANS story, relative urls being on `host`
+    pub fn push_story(&mut self, story: &Value, host: &str) {
+        let promo = &story["promo_items"]["basic"];
+        if let Some(img) = Image::from_ans(promo, host) {
+            self.imgs.push(img)
+        }
+
+        for credit in story["credits"]["by"].as_array().into_iter().flatten() {
+            if let Some(photo) = Image::from_ans(&credit["image"], host) {
+                self.imgs.push(photo);
+            }
+        }
+
+        let lead_art = &story["promo_items"]["lead_art"];
+        if matches!(lead_art["type"].as_str(), Some("video" | "audio")) {
+            self.push_media(lead_art, host);
+        }
+
+        if let Some(contents) = story["content_elements"].as_array() {
+            for content in contents {
+                match content["type"].as_str() {
+                    Some("image") => {
+                        if let Some(img_url) = content["content"].as_str() {
+                            self.imgs.push(Image::plain(img_url, host));
+                        }
+                        if let Some(img) = Image::from_ans(content, host) {
+                            self.imgs.push(img);
+                        }
+                    }
+                    Some("video" | "audio") => self.push_media(content, host),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Collect the files of an ANS `video` or `audio` element
+    fn push_media(&mut self, element: &Value, host: &str) {
+        if let Some(url) = media_url(element) {
+            self.media.push(url.to_owned());
+        }
+        if let Some(poster) = Image::from_ans(&element["promo_image"], host) {
+            self.imgs.push(poster);
+        }
+    }
+
+    pub fn extend(&mut self, other: Objects) {
+        self.imgs.extend(other.imgs);
+        self.media.extend(other.media);
+    }
+}
+
+pub trait Source: Send + Sync {
+    /// Host serving the API, requests to it are retried
+    fn host(&self) -> String;
+
+    /// Max `offset + size` the backend pages through, longer windows get split
+    fn max_window(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether listings hold the latest stories whatever the window, e.g. a feed.
+    /// A crawl fetches such a source once, not once per month.
+    fn latest_only(&self) -> bool {
+        false
+    }
+
+    /// Url listing the stories of `query`
+    fn list_url(&self, site: &Site, query: &Query) -> String;
+
+    /// Parse a listing response of `query`
+    fn parse_list(
+        &self,
+        site: &Site,
+        query: &Query,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Listing, Box<dyn Error>>;
+
+    /// Url of a single story, `None` if the backend can't fetch one
+    fn story_url(&self, site: &Site, story: StoryRef) -> Option<String>;
+
+    /// Parse a single story response
+    fn parse_story(&self, site: &Site, body: &[u8]) -> Result<Value, Box<dyn Error>>;
+
+    /// Sitemaps and feeds listing the stories of `site`, see [`crate::discover`]
+    fn discovery(&self, _site: &Site) -> Vec<String> {
+        vec![]
+    }
+
+    /// Whether a sitemap of a sitemap index may list stories
+    fn follow_sitemap(&self, _url: &str) -> bool {
+        true
+    }
+
+    /// Whether a discovered url is a story, not a section or tag page
+    fn is_story(&self, _url: &str) -> bool {
+        true
+    }
+
+    /// Url of the section hierarchy of `site`, `None` if the backend has none
+    fn sections_url(&self, _site: &Site) -> Option<String> {
+        None
+    }
+
+    /// Parse the section hierarchy, parents before children
+    fn parse_sections(&self, _site: &Site, _body: &[u8]) -> Result<Vec<Section>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+
+    /// Images and media files of a story returned by this source
+    fn objects(&self, story: &Value) -> Objects {
+        let mut objs = Objects::default();
+        objs.push_story(story, &self.host());
+        objs
+    }
+}
+
+/// Backend of a site, as configured in the registry, e.g. `{"type": "wordpress", "base": "https://…"}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Backend {
+    Arc(ArcXp),
+    WordPress(WordPress),
+    Rss(Rss),
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Arc(ArcXp::default())
+    }
+}
+
+impl Backend {
+    pub fn source(&self) -> &dyn Source {
+        match self {
+            Backend::Arc(s) => s,
+            Backend::WordPress(s) => s,
+            Backend::Rss(s) => s,
+        }
+    }
+}
+
+/// A story of a backend without ANS, see [`Story::into_ans`]
+#[derive(Debug, Default)]
+struct Story {
+    id: String,
+    /// absolute url of the story
+    url: String,
+    published: Option<Timestamp>,
+    updated: Option<Timestamp>,
+    headline: String,
+    /// plain text
+    description: String,
+    /// ANS `credits.by` entries
+    credits: Vec<Value>,
+    /// (slug, name)
+    section: Option<(String, String)>,
+    /// (url, caption)
+    image: Option<(String, String)>,
+    body_html: String,
+    /// (url, mime type) of attached audio and video files
+    media: Vec<(String, String)>,
+}
+
+impl Story {
+    fn into_ans(self, site: &Site) -> Result<Value, Box<dyn Error>> {
+        let published = self.published.ok_or("story without publish date")?;
+        let website_url = website_url(site, &self.url)
+            .ok_or_else(|| format!("story without url: {}", self.id))?;
+        let (section_id, section_name) = self
+            .section
+            .map(|(slug, name)| (format!("/{}/{slug}", site.prefix), name))
+            .unwrap_or_default();
+
+        let mut content_elements = html_elements(&self.body_html);
+        let mut lead_art = Value::Null;
+        for (url, mime) in self.media {
+            let kind = if mime.starts_with("video/") {
+                "video"
+            } else if mime.starts_with("audio/") {
+                "audio"
+            } else {
+                continue;
+            };
+            let element = json!({
+                "type": kind,
+                "streams": [{ "url": url, "stream_type": mime.split_once('/').unwrap().1 }],
+            });
+            if lead_art.is_null() {
+                lead_art = element;
+            } else {
+                content_elements.push(element);
+            }
+        }
+
+        let mut story = json!({
+            "_id": self.id,
+            "type": "story",
+            "canonical_url": self.url,
+            "display_date": published.to_string(),
+            "last_updated_date": self.updated.unwrap_or(published).to_string(),
+            "headlines": { "basic": self.headline },
+            "description": { "basic": self.description },
+            "credits": { "by": self.credits },
+            "websites": {
+                &site.id: {
+                    "website_url": website_url,
+                    "website_section": { "_id": section_id, "name": section_name },
+                }
+            },
+            "content_elements": content_elements,
+        });
+        if let Some((url, caption)) = self.image {
+            story["promo_items"]["basic"] =
+                json!({ "type": "image", "url": url, "caption": caption });
+        }
+        if !lead_art.is_null() {
+            story["promo_items"]["lead_art"] = lead_art;
+        }
+        Ok(story)
+    }
+}
+
+/// ANS `content_elements` of an HTML body: paragraphs become `text` (inline markup
+/// kept), headings `header`, images `image`, lists and quotes are kept as `text`.
+fn html_elements(html: &str) -> Vec<Value> {
+    let mut elements = vec![];
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut elements, rest);
+            break;
+        };
+        push_text(&mut elements, &rest[..start]);
+        rest = &rest[start..];
+
+        let name: String = rest[1..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let Some(open_end) = rest.find('>') else {
+            break;
+        };
+        if name.is_empty() || name == "img" || name == "br" || name == "hr" {
+            if name == "img" {
+                push_img(&mut elements, &rest[..=open_end], "");
+            }
+            rest = &rest[open_end + 1..];
+            continue;
+        }
+
+        // the close tag comes after the open one, which may quote it in an attribute
+        let after = &rest[open_end + 1..];
+        let close = format!("</{name}>");
+        let (inner, outer_end) = if rest[..open_end].ends_with('/') {
+            ("", open_end + 1)
+        } else {
+            match after.to_ascii_lowercase().find(&close) {
+                Some(i) => (&after[..i], open_end + 1 + i + close.len()),
+                None => (after, rest.len()),
+            }
+        };
+        match name.as_str() {
+            "p" | "div" | "section" if inner.contains("<img") => {
+                elements.extend(html_elements(inner));
+            }
+            "p" => push_text(&mut elements, inner),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let text = strip_tags(inner);
+                if !text.is_empty() {
+                    elements.push(json!({ "type": "header", "content": text }));
+                }
+            }
+            "figure" => {
+                let caption = inner
+                    .split_once("<figcaption")
+                    .and_then(|(_, c)| c.split_once('>'))
+                    .map(|(_, c)| strip_tags(c.split("</figcaption>").next().unwrap_or_default()))
+                    .unwrap_or_default();
+                if let Some(i) = inner.find("<img") {
+                    push_img(&mut elements, &inner[i..], &caption);
+                }
+            }
+            "script" | "style" | "iframe" | "noscript" => {}
+            "div" | "section" | "article" => elements.extend(html_elements(inner)),
+            _ => push_text(&mut elements, &rest[..outer_end]),
+        }
+        rest = &rest[outer_end..];
+    }
+    elements
+}
+
+fn push_text(elements: &mut Vec<Value>, html: &str) {
+    let html = html.trim();
+    if !strip_tags(html).is_empty() {
+        elements.push(json!({ "type": "text", "content": html }));
+    }
+}
+
+fn push_img(elements: &mut Vec<Value>, tag: &str, caption: &str) {
+    if let Some(src) = attr(tag, "src") {
+        let caption = if caption.is_empty() {
+            attr(tag, "alt").unwrap_or_default()
+        } else {
+            caption.to_owned()
+        };
+        elements.push(json!({ "type": "image", "url": src, "caption": caption }));
+    }
+}
+
+/// Value of the first `name="…"` attribute in `tag`
+fn attr(tag: &str, name: &str) -> Option<String> {
+    let end = tag.find('>').unwrap_or(tag.len());
+    let tag = &tag[..end];
+    let pattern = format!(" {name}=");
+    let i = tag.find(&pattern)? + pattern.len();
+    let value = &tag[i..];
+    let quote = value.chars().next()?;
+    let value = if quote == '"' || quote == '\'' {
+        value[1..].split(quote).next()?
+    } else {
+        value.split([' ', '/', '>']).next()?
+    };
+    Some(unescape(value))
+}
+
+/// Plain text of an HTML fragment
+pub fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    unescape(text.trim())
+}
+
+/// Decode the HTML entities CMSes put in titles and excerpts
+pub fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.bytes().take(12).position(|b| b == b';') else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `website_url` of a story of `site` at `url`: its path, starting with the site
+/// prefix as the keys of `rfa` and the index do
+pub fn website_url(site: &Site, url: &str) -> Option<String> {
+    let path = reqwest::Url::parse(url)
+        .map(|u| u.path().trim_matches('/').to_owned())
+        .unwrap_or_else(|_| url.trim_matches('/').to_owned());
+    if path.is_empty() {
+        None
+    } else if path.starts_with(&format!("{}/", site.prefix)) {
+        Some(format!("/{path}"))
+    } else {
+        Some(format!("/{}/{path}", site.prefix))
+    }
+}
+
+/// Host of a url, empty if it has none
+fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_owned()))
+        .unwrap_or_default()
+}
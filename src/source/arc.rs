@@ -0,0 +1,270 @@
+//! Arc XP, the CMS of www.rfa.org, through the PageBuilder content API.
+//!
+//! Stories are ANS already and stored as returned, projected by [`story_filter`]
+//! unless the source asks for the complete documents, see [`StoryFilter`].
+
+use std::error::Error;
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use urlencoding::encode;
+
+use super::{Listing, Query, Source, StoryRef};
+use crate::{section::Section, site::Site};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArcXp {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default, skip_serializing_if = "StoryFilter::is_default")]
+    pub filter: StoryFilter,
+}
+
+/// Projection of the stories requested from the content API, e.g. `"full"` or
+/// `{"custom": "_id,headlines{basic},websites{$site{website_url}}"}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoryFilter {
+    /// the fields the web uses, see [`story_filter`]
+    #[default]
+    Default,
+    /// the complete ANS document, nothing left out
+    Full,
+    /// fields of a story, `$site` standing for the website id
+    Custom(String),
+}
+
+impl StoryFilter {
+    fn is_default(&self) -> bool {
+        *self == StoryFilter::Default
+    }
+
+    /// Check a custom filter keeps the fields the spider needs to store a story:
+    /// `_id`, `display_date` and `websites{$site{website_url}}`
+    pub fn check(&self) -> Result<(), String> {
+        let StoryFilter::Custom(fields) = self else {
+            return Ok(());
+        };
+        let fields: String = fields.split_whitespace().collect();
+        let website_url = subfield(&fields, "websites")
+            .and_then(|websites| subfield(websites, "$site"))
+            .and_then(|site| subfield(site, "website_url"));
+        for (field, found) in [
+            ("_id", subfield(&fields, "_id").is_some()),
+            ("display_date", subfield(&fields, "display_date").is_some()),
+            ("websites{$site{website_url}}", website_url.is_some()),
+        ] {
+            if !found {
+                return Err(format!("the story filter lacks {field}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// `filter` query parameter around the story fields, `None` for complete stories
+    fn param(&self, site: &str, wrap: impl Fn(String) -> String) -> Option<String> {
+        let fields = match self {
+            StoryFilter::Default => story_filter(site),
+            StoryFilter::Full => return None,
+            // whitespace only helps reading the file
+            StoryFilter::Custom(fields) => fields
+                .split_whitespace()
+                .collect::<String>()
+                .replace("$site", site),
+        };
+        Some(format!("&filter={}", encode(&wrap(fields))))
+    }
+}
+
+/// Fields selected within the field `name` of a filter, e.g. `b,c` for `a` in
+/// `a{b,c},d`, empty for a plain field, `None` if not selected
+fn subfield<'a>(fields: &'a str, name: &str) -> Option<&'a str> {
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in fields.char_indices().chain([(fields.len(), ',')]) {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                let field = &fields[start..i];
+                let (field_name, inner) = match field.split_once('{') {
+                    Some((field_name, inner)) => {
+                        (field_name, inner.strip_suffix('}').unwrap_or(inner))
+                    }
+                    None => (field, ""),
+                };
+                if field_name == name {
+                    return Some(inner);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn default_host() -> String {
+    "www.rfa.org".to_owned()
+}
+
+impl Default for ArcXp {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            filter: StoryFilter::default(),
+        }
+    }
+}
+
+impl Source for ArcXp {
+    fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    /// The search backend pages through 10,000 results at most
+    fn max_window(&self) -> Option<u64> {
+        Some(10_000)
+    }
+
+    fn list_url(&self, site: &Site, query: &Query) -> String {
+        let query_json = json!({
+            "feature": "results-list",
+            "offset": query.offset,
+            "query": format!("display_date:[{} TO {}]", query.begin, query.end),
+            "size": query.size
+        });
+        let query_json = query_json.to_string();
+        let encoded_query = encode(&query_json);
+        let filter = self
+            .filter
+            .param(&site.id, |fields| {
+                format!("{{content_elements{{{fields}}},count,next}}")
+            })
+            .unwrap_or_default();
+
+        format!(
+            "https://{}/pf/api/v3/content/fetch/story-feed-query?query={}{}&d=147&mxId=00000000&_website={}",
+            self.host, encoded_query, filter, site.id
+        )
+    }
+
+    fn parse_list(
+        &self,
+        _site: &Site,
+        query: &Query,
+        _headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Listing, Box<dyn Error>> {
+        let json: Value = serde_json::from_slice(body)?;
+        // an error body has no count, it must not pass for an empty window
+        let count = json["count"].as_u64().ok_or("listing without count")?;
+        Ok(Listing {
+            count: Some(count),
+            next: json["next"].as_u64().filter(|next| *next > query.offset),
+            stories: json["content_elements"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+
+    fn story_url(&self, site: &Site, story: StoryRef) -> Option<String> {
+        let query = match story {
+            StoryRef::Path(path) => json!({ "website_url": path, "website": site.id }),
+            StoryRef::Id(id) => json!({ "_id": id, "website": site.id }),
+        };
+        let query = encode(&query.to_string()).into_owned();
+        let filter = self
+            .filter
+            .param(&site.id, |fields| format!("{{{fields}}}"))
+            .unwrap_or_default();
+
+        Some(format!(
+            "https://{}/pf/api/v3/content/fetch/content-api?query={}{}&d=147&mxId=00000000&_website={}",
+            self.host, query, filter, site.id
+        ))
+    }
+
+    /// The outbound feeds of Arc: the sitemap index and the latest stories
+    fn discovery(&self, site: &Site) -> Vec<String> {
+        vec![
+            format!(
+                "https://{}/arc/outboundfeeds/sitemap-index/?outputType=xml&_website={}",
+                self.host, site.id
+            ),
+            format!(
+                "https://{}/arc/outboundfeeds/rss/?outputType=xml&_website={}",
+                self.host, site.id
+            ),
+        ]
+    }
+
+    /// Stories are at least `/<prefix>/<section>/<slug>`
+    fn is_story(&self, url: &str) -> bool {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        let path = path.split_once("://").map_or(path, |(_, rest)| rest);
+        path.trim_end_matches('/').matches('/').count() >= 3
+    }
+
+    /// The site service hierarchy, the tree of the site navigation
+    fn sections_url(&self, site: &Site) -> Option<String> {
+        let query = json!({ "hierarchy": "default", "sectionId": format!("/{}", site.prefix) });
+        Some(format!(
+            "https://{}/pf/api/v3/content/fetch/site-service-hierarchy?query={}&d=147&mxId=00000000&_website={}",
+            self.host,
+            encode(&query.to_string()),
+            site.id
+        ))
+    }
+
+    fn parse_sections(&self, _site: &Site, body: &[u8]) -> Result<Vec<Section>, Box<dyn Error>> {
+        let root: Value = serde_json::from_slice(body)?;
+        let mut sections = vec![];
+        let mut stack = vec![(None, &root)];
+        while let Some((parent, node)) = stack.pop() {
+            // skip the plain links of the navigation
+            let Some(id) = node["_id"].as_str().filter(|id| id.starts_with('/')) else {
+                continue;
+            };
+            let description = [&node["site"]["site_description"], &node["description"]]
+                .into_iter()
+                .find_map(|d| d.as_str().filter(|d| !d.is_empty()))
+                .map(str::to_owned);
+            sections.push(Section {
+                id: id.to_owned(),
+                name: node["name"].as_str().unwrap_or(id).to_owned(),
+                parent,
+                description,
+                url: node["site"]["site_url"]
+                    .as_str()
+                    .filter(|u| u.starts_with('/'))
+                    .unwrap_or(id)
+                    .trim_end_matches('/')
+                    .to_owned(),
+                order: node["order"]["default"].as_u64().unwrap_or_default() as u32,
+            });
+            if let Some(children) = node["children"].as_array() {
+                stack.extend(children.iter().rev().map(|c| (Some(id.to_owned()), c)));
+            }
+        }
+        Ok(sections)
+    }
+
+    fn parse_story(&self, _site: &Site, body: &[u8]) -> Result<Value, Box<dyn Error>> {
+        let json: Value = serde_json::from_slice(body)?;
+        if json["_id"].as_str().is_none() {
+            return Err(format!("not found: {json}").into());
+        }
+        Ok(json)
+    }
+}
+
+/// Fields kept of a story, shared by the feed and the single story queries
+fn story_filter(site: &str) -> String {
+    format!(
+        r#"_id,credits{{by{{_id,additional_properties{{original{{bio,byline,longBio}}}},description,image{{_id,auth{{1}},url}},name,slug,type,url}}}},description{{basic}},display_date,last_updated_date,headlines{{basic}},label{{basic{{display,text,url}}}},owner{{sponsored}},promo_items{{basic{{_id,auth{{1}},type,url,caption,width,height,credits{{by{{name}},affiliation{{name}}}}}},lead_art{{_id,type,duration,headlines{{basic}},promo_image{{url}},promo_items{{basic{{_id,auth{{1}},type,url}}}},streams{{url,stream_type,bitrate}}}},type}},type,websites{{{}{{website_section{{_id,name}},website_url}}}},content_elements{{_id,auth{{1}},type,content,url,caption{{basic}},width,height,credits{{by{{name}},affiliation{{name}}}},headlines{{basic}},description{{basic}},duration,promo_image{{url}},streams{{url,stream_type,bitrate}}}}"#,
+        site
+    )
+}
@@ -0,0 +1,204 @@
+//! Plain RSS 2.0 feeds.
+//!
+//! A feed only holds the latest items, so a window lists the items of the feed
+//! published in it, and single stories can't be fetched. A crawl fetches the feed
+//! once for all its months. Crawl such sites often, e.g. with `--refresh-days`, to
+//! miss nothing.
+
+use std::error::Error;
+
+use jiff::Timestamp;
+use quick_xml::{Reader, events::Event};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::warn;
+
+use super::{Listing, Query, Source, Story, StoryRef, host_of, strip_tags, unescape};
+use crate::site::Site;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rss {
+    /// url of the feed
+    pub feed: String,
+}
+
+impl Source for Rss {
+    fn host(&self) -> String {
+        host_of(&self.feed)
+    }
+
+    fn latest_only(&self) -> bool {
+        true
+    }
+
+    fn list_url(&self, _site: &Site, _query: &Query) -> String {
+        self.feed.clone()
+    }
+
+    fn parse_list(
+        &self,
+        site: &Site,
+        query: &Query,
+        _headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Listing, Box<dyn Error>> {
+        let mut stories = vec![];
+        let mut count = 0;
+        for story in parse_feed(body)? {
+            let Some(day) = story
+                .published
+                .map(|ts| ts.to_zoned(jiff::tz::TimeZone::UTC).date())
+            else {
+                continue;
+            };
+            if day < query.begin || day > query.end {
+                continue;
+            }
+            count += 1;
+            // a bad item is skipped, not the rest of the feed
+            match story.into_ans(site) {
+                Ok(story) => stories.push(story),
+                Err(e) => warn!("Skipping an item of {}: {e}", self.feed),
+            }
+        }
+        Ok(Listing {
+            count: Some(count),
+            next: None,
+            stories,
+        })
+    }
+
+    fn story_url(&self, _site: &Site, _story: StoryRef) -> Option<String> {
+        None
+    }
+
+    fn discovery(&self, _site: &Site) -> Vec<String> {
+        vec![self.feed.clone()]
+    }
+
+    fn parse_story(&self, _site: &Site, _body: &[u8]) -> Result<Value, Box<dyn Error>> {
+        Err("a feed has no single stories".into())
+    }
+}
+
+fn parse_date(s: &str) -> Option<Timestamp> {
+    let s = s.trim();
+    jiff::fmt::rfc2822::parse(s)
+        .map(|z| z.timestamp())
+        .ok()
+        .or_else(|| s.parse().ok())
+}
+
+/// Items of a feed
+fn parse_feed(body: &[u8]) -> Result<Vec<Story>, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(body);
+    let mut buf = vec![];
+    let mut stories = vec![];
+    let mut item: Option<Story> = None;
+    // element whose text is being read, and the text
+    let mut field = String::new();
+    let mut text = String::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                if name == "item" {
+                    item = Some(Story::default());
+                } else if item.is_some() {
+                    if let Some(story) = item.as_mut() {
+                        push_media(story, &name, &e)?;
+                    }
+                    field = name;
+                    text.clear();
+                }
+            }
+            Event::Empty(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                if let Some(story) = item.as_mut() {
+                    push_media(story, &name, &e)?;
+                }
+            }
+            Event::Text(t) => text.push_str(&t.decode()?),
+            Event::GeneralRef(r) => text.push_str(&unescape(&format!("&{};", r.decode()?))),
+            Event::CData(c) => text.push_str(&c.decode()?),
+            Event::End(e) => {
+                let name = e.name();
+                let name = String::from_utf8_lossy(name.as_ref());
+                if name == "item" {
+                    if let Some(mut story) = item.take() {
+                        if story.id.is_empty() {
+                            story.id = story.url.clone();
+                        }
+                        stories.push(story);
+                    }
+                } else if let Some(story) = item.as_mut()
+                    && name == field
+                {
+                    let value = text.trim().to_owned();
+                    match field.as_str() {
+                        "title" => story.headline = strip_tags(&value),
+                        "link" => story.url = value,
+                        "guid" => story.id = value,
+                        "pubDate" | "dc:date" => story.published = parse_date(&value),
+                        "description" => {
+                            story.description = strip_tags(&value);
+                            if story.body_html.is_empty() {
+                                story.body_html = value;
+                            }
+                        }
+                        "content:encoded" => story.body_html = value,
+                        "dc:creator" | "author" => story.credits.push(json!({ "name": value })),
+                        "category" if story.section.is_none() => {
+                            let slug = value.to_lowercase().replace(' ', "-");
+                            story.section = Some((slug, value));
+                        }
+                        _ => {}
+                    }
+                    field.clear();
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(stories)
+}
+
+/// `enclosure` and `media:content` of an item, images become its promo image
+fn push_media(
+    story: &mut Story,
+    name: &str,
+    e: &quick_xml::events::BytesStart,
+) -> Result<(), Box<dyn Error>> {
+    if name != "enclosure" && name != "media:content" {
+        return Ok(());
+    }
+    let attr = |key: &str| -> Result<Option<String>, Box<dyn Error>> {
+        Ok(match e.try_get_attribute(key)? {
+            Some(a) => Some(a.unescape_value()?.into_owned()),
+            None => None,
+        })
+    };
+    let Some(url) = attr("url")? else {
+        return Ok(());
+    };
+    let mime = match attr("type")? {
+        Some(mime) => mime,
+        None => match attr("medium")?.as_deref() {
+            Some("image") => "image/".to_owned(),
+            Some("video") => "video/mp4".to_owned(),
+            Some("audio") => "audio/mpeg".to_owned(),
+            _ => return Ok(()),
+        },
+    };
+    if mime.starts_with("image/") {
+        if story.image.is_none() {
+            story.image = Some((url, String::new()));
+        }
+    } else {
+        story.media.push((url, mime));
+    }
+    Ok(())
+}
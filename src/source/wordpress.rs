@@ -0,0 +1,221 @@
+//! WordPress sites, through the REST API (`/wp-json/wp/v2/posts`).
+//!
+//! Author, featured image and categories come embedded (`_embed`), the rendered
+//! HTML content is split into ANS elements.
+
+use std::error::Error;
+
+use jiff::{Timestamp, civil::DateTime, tz::TimeZone};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::warn;
+use urlencoding::encode;
+
+use super::{Listing, Query, Source, Story, StoryRef, host_of, strip_tags};
+use crate::{section::Section, site::Site};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordPress {
+    /// root of the site, e.g. `https://www.example.org`
+    pub base: String,
+}
+
+const EMBED: &str = "_embed=author,wp:featuredmedia,wp:term";
+
+impl Source for WordPress {
+    fn host(&self) -> String {
+        host_of(&self.base)
+    }
+
+    fn list_url(&self, _site: &Site, query: &Query) -> String {
+        // `before` is exclusive
+        let before = query.end.tomorrow().unwrap_or(query.end);
+        format!(
+            "{}/wp-json/wp/v2/posts?after={}T00:00:00&before={}T00:00:00&orderby=date&order=asc&per_page={}&offset={}&{EMBED}",
+            self.base.trim_end_matches('/'),
+            query.begin,
+            before,
+            query.size.min(100),
+            query.offset,
+        )
+    }
+
+    fn parse_list(
+        &self,
+        site: &Site,
+        query: &Query,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Listing, Box<dyn Error>> {
+        let posts: Vec<Value> = serde_json::from_slice(body)?;
+        let count = headers
+            .get("x-wp-total")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let end = query.offset + posts.len() as u64;
+        let next = match count {
+            Some(count) => (end < count).then_some(end),
+            None => (posts.len() as u64 >= query.size.min(100)).then_some(end),
+        };
+        // a bad post is skipped, not the rest of the page
+        let stories = posts
+            .iter()
+            .filter_map(|post| match normalize(site, post) {
+                Ok(story) => Some(story),
+                Err(e) => {
+                    warn!("Skipping post {} of {}: {e}", post["id"], self.base);
+                    None
+                }
+            })
+            .collect();
+        Ok(Listing {
+            count,
+            next,
+            stories,
+        })
+    }
+
+    fn story_url(&self, _site: &Site, story: StoryRef) -> Option<String> {
+        let base = self.base.trim_end_matches('/');
+        Some(match story {
+            StoryRef::Id(id) => format!("{base}/wp-json/wp/v2/posts/{}?{EMBED}", encode(id)),
+            StoryRef::Path(path) => {
+                let slug = path
+                    .trim_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default();
+                format!("{base}/wp-json/wp/v2/posts?slug={}&{EMBED}", encode(slug))
+            }
+        })
+    }
+
+    /// The core sitemap (WordPress 5.5+) and the feed
+    fn discovery(&self, _site: &Site) -> Vec<String> {
+        let base = self.base.trim_end_matches('/');
+        vec![format!("{base}/wp-sitemap.xml"), format!("{base}/feed/")]
+    }
+
+    /// Skip the sitemaps of pages, taxonomies and users
+    fn follow_sitemap(&self, url: &str) -> bool {
+        url.contains("wp-sitemap-posts-post-")
+    }
+
+    /// The categories, the first 100 of them
+    fn sections_url(&self, _site: &Site) -> Option<String> {
+        let base = self.base.trim_end_matches('/');
+        Some(format!(
+            "{base}/wp-json/wp/v2/categories?per_page=100&orderby=id&_fields=id,name,slug,parent,description"
+        ))
+    }
+
+    fn parse_sections(&self, site: &Site, body: &[u8]) -> Result<Vec<Section>, Box<dyn Error>> {
+        let categories: Vec<Value> = serde_json::from_slice(body)?;
+        // stories refer to their category by slug, see `normalize`
+        let id_of = |wp_id: &Value| {
+            categories
+                .iter()
+                .find(|c| c["id"] == *wp_id)
+                .and_then(|c| c["slug"].as_str())
+                .map(|slug| format!("/{}/{slug}", site.prefix))
+        };
+        let mut sections = vec![];
+        for (order, c) in categories.iter().enumerate() {
+            let Some(id) = id_of(&c["id"]) else {
+                continue;
+            };
+            sections.push(Section {
+                url: id.clone(),
+                id,
+                name: strip_tags(c["name"].as_str().unwrap_or_default()),
+                parent: id_of(&c["parent"]),
+                description: c["description"]
+                    .as_str()
+                    .filter(|d| !d.is_empty())
+                    .map(strip_tags),
+                order: order as u32,
+            });
+        }
+        Ok(sections)
+    }
+
+    fn parse_story(&self, site: &Site, body: &[u8]) -> Result<Value, Box<dyn Error>> {
+        let json: Value = serde_json::from_slice(body)?;
+        // by slug, the API answers a list
+        let post = match &json {
+            Value::Array(posts) => posts.first().ok_or("not found")?,
+            post => post,
+        };
+        normalize(site, post)
+    }
+}
+
+/// Dates of the API are `*_gmt` without offset
+fn gmt(value: &Value) -> Option<Timestamp> {
+    let dt: DateTime = value.as_str()?.parse().ok()?;
+    dt.to_zoned(TimeZone::UTC).ok().map(|z| z.timestamp())
+}
+
+/// ANS credit of an embedded user, the largest avatar as photo
+fn credit(user: &Value) -> Option<Value> {
+    let name = user["name"].as_str()?;
+    let photo = user["avatar_urls"].as_object().and_then(|avatars| {
+        avatars
+            .iter()
+            .max_by_key(|(size, _)| size.parse::<u32>().unwrap_or_default())
+            .and_then(|(_, url)| url.as_str())
+    });
+    let mut credit = json!({
+        "type": "author",
+        "name": strip_tags(name),
+        "slug": user["slug"],
+        "url": user["link"],
+        "description": user["description"],
+    });
+    if let Some(photo) = photo {
+        credit["image"] = json!({ "url": photo });
+    }
+    Some(credit)
+}
+
+fn normalize(site: &Site, post: &Value) -> Result<Value, Box<dyn Error>> {
+    let embedded = &post["_embedded"];
+    let featured = &embedded["wp:featuredmedia"][0];
+    let image = featured["source_url"].as_str().map(|url| {
+        let caption = strip_tags(featured["caption"]["rendered"].as_str().unwrap_or_default());
+        (url.to_owned(), caption)
+    });
+    // the first term list holds the categories
+    let section = embedded["wp:term"][0][0]["slug"].as_str().map(|slug| {
+        let name = embedded["wp:term"][0][0]["name"].as_str().unwrap_or(slug);
+        (slug.to_owned(), strip_tags(name))
+    });
+
+    let id = match &post["id"] {
+        Value::Number(n) => n.to_string(),
+        id => id.as_str().ok_or("post without id")?.to_owned(),
+    };
+    Story {
+        id,
+        url: post["link"].as_str().unwrap_or_default().to_owned(),
+        published: gmt(&post["date_gmt"]),
+        updated: gmt(&post["modified_gmt"]),
+        headline: strip_tags(post["title"]["rendered"].as_str().unwrap_or_default()),
+        description: strip_tags(post["excerpt"]["rendered"].as_str().unwrap_or_default()),
+        credits: embedded["author"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(credit)
+            .collect(),
+        section,
+        image,
+        body_html: post["content"]["rendered"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned(),
+        media: vec![],
+    }
+    .into_ans(site)
+}
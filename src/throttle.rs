@@ -0,0 +1,126 @@
+//! Politeness controls shared by all crawling tasks: request rate, jitter,
+//! a daily request budget and a bandwidth cap.
+
+use std::{sync::Mutex, time::Duration};
+
+use jiff::{Zoned, civil::Date, tz::TimeZone};
+use tokio::time::{Instant, sleep, sleep_until};
+use tracing::warn;
+
+#[derive(Debug, Default)]
+pub struct Throttle {
+    /// min time between two requests
+    interval: Option<Duration>,
+    /// max random delay added before each request
+    jitter: Duration,
+    /// max requests per UTC day
+    daily_budget: Option<u64>,
+    bytes_per_sec: Option<u64>,
+    next_request: Mutex<Option<Instant>>,
+    next_bytes: Mutex<Option<Instant>>,
+    /// (UTC day, requests sent that day)
+    used: Mutex<(Date, u64)>,
+}
+
+impl Throttle {
+    pub fn new(
+        rps: Option<f64>,
+        jitter: Duration,
+        daily_budget: Option<u64>,
+        bytes_per_sec: Option<u64>,
+    ) -> Self {
+        Self {
+            interval: rps
+                .filter(|rps| *rps > 0.0)
+                .map(|rps| Duration::from_secs_f64(1.0 / rps)),
+            jitter,
+            daily_budget,
+            bytes_per_sec: bytes_per_sec.filter(|b| *b > 0),
+            used: Mutex::new((today(), 0)),
+            ..Default::default()
+        }
+    }
+
+    /// Restore the requests already sent on `day`, e.g. by a previous run. Never
+    /// lowers the count of today, another crawl of the daemon may be sending more.
+    pub fn set_used(&self, day: Date, n: u64) {
+        let mut used = self.used.lock().unwrap();
+        if used.0 == day {
+            used.1 = used.1.max(n);
+        } else {
+            *used = (day, n);
+        }
+    }
+
+    /// Requests sent today
+    pub fn used(&self) -> (Date, u64) {
+        let mut used = self.used.lock().unwrap();
+        roll_over(&mut used);
+        *used
+    }
+
+    /// Count a request against the budget, or return the day it is used up on.
+    /// Checked and counted under one lock, so concurrent tasks can't overshoot it.
+    fn take(&self, budget: u64) -> Result<(), Date> {
+        let mut used = self.used.lock().unwrap();
+        roll_over(&mut used);
+        if used.1 >= budget {
+            return Err(used.0);
+        }
+        used.1 += 1;
+        Ok(())
+    }
+
+    /// Wait for the next request slot. Once the daily budget is used up, waits
+    /// for the next UTC day.
+    pub async fn request(&self) {
+        if let Some(budget) = self.daily_budget {
+            while let Err(day) = self.take(budget) {
+                let tomorrow = day.tomorrow().unwrap().to_zoned(TimeZone::UTC).unwrap();
+                let wait = tomorrow.duration_since(&Zoned::now()).unsigned_abs();
+                warn!("Daily budget of {budget} requests used up, waiting until {tomorrow}");
+                sleep(wait + Duration::from_secs(1)).await;
+            }
+        }
+
+        if let Some(interval) = self.interval {
+            let slot = reserve(&self.next_request, interval);
+            sleep_until(slot).await;
+        }
+
+        if !self.jitter.is_zero() {
+            let ms = fastrand::u64(0..=self.jitter.as_millis() as u64);
+            sleep(Duration::from_millis(ms)).await;
+        }
+    }
+
+    /// Account for `n` downloaded bytes, waiting to stay under the bandwidth cap
+    pub async fn consume(&self, n: usize) {
+        if let Some(rate) = self.bytes_per_sec {
+            let cost = Duration::from_secs_f64(n as f64 / rate as f64);
+            let slot = reserve(&self.next_bytes, cost);
+            sleep_until(slot + cost).await;
+        }
+    }
+}
+
+/// Take the next free slot and push it back by `cost`
+fn reserve(next: &Mutex<Option<Instant>>, cost: Duration) -> Instant {
+    let mut next = next.lock().unwrap();
+    let now = Instant::now();
+    let slot = next.map_or(now, |n| n.max(now));
+    *next = Some(slot + cost);
+    slot
+}
+
+/// Start counting again on a new day
+fn roll_over(used: &mut (Date, u64)) {
+    let today = today();
+    if used.0 != today {
+        *used = (today, 0);
+    }
+}
+
+fn today() -> Date {
+    Zoned::now().with_time_zone(TimeZone::UTC).date()
+}
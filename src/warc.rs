@@ -0,0 +1,290 @@
+//! WARC 1.1 captures of the HTTP exchanges of the spider, with a CDX index, for
+//! ingestion by institutional archives and replay tools (pywb, OpenWayback…).
+//!
+//! Every record is a gzip member of its own, so files can be read at the offsets of
+//! the index. Files rotate once they pass a size, as `<prefix>-<seq>.warc.gz`, and
+//! `index.cdx` lists the responses of all of them, sorted by [`WarcWriter::finish`].
+//!
+//! reqwest decodes the body, so the recorded response is the decoded one: its
+//! `Content-Encoding` and `Transfer-Encoding` headers are dropped and
+//! `Content-Length` is the decoded length.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use data_encoding::BASE32;
+use flate2::{Compression, write::GzEncoder};
+use http::{HeaderMap, StatusCode, Version, header};
+use jiff::Timestamp;
+use sha1::{Digest, Sha1};
+
+const CDX_HEADER: &str = " CDX N b a m s k r M S V g";
+
+/// Headers describing the transfer rather than the decoded body
+const SKIPPED_HEADERS: [header::HeaderName; 3] = [
+    header::CONTENT_ENCODING,
+    header::TRANSFER_ENCODING,
+    header::CONTENT_LENGTH,
+];
+
+pub struct WarcWriter {
+    dir: PathBuf,
+    /// file name prefix, e.g. `rfa-20250101120000`
+    prefix: String,
+    /// size after which the next record goes to a new file
+    max_size: u64,
+    current: Mutex<Option<Current>>,
+    cdx: Mutex<File>,
+}
+
+struct Current {
+    file: File,
+    name: String,
+    seq: u32,
+}
+
+impl WarcWriter {
+    pub fn new(dir: &Path, max_size: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let cdx = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("index.cdx"))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            prefix: format!("rfa-{}", Timestamp::now().strftime("%Y%m%d%H%M%S")),
+            max_size,
+            current: Mutex::new(None),
+            cdx: Mutex::new(cdx),
+        })
+    }
+
+    /// Write the request and the response of `url`, and index the response
+    pub fn write<B: Read + Seek>(
+        &self,
+        url: &str,
+        status: StatusCode,
+        version: Version,
+        headers: &HeaderMap,
+        body: &mut B,
+    ) -> io::Result<()> {
+        // the digest goes before the body, hash it first
+        let mut hasher = Sha1::new();
+        let len = io::copy(body, &mut HashWriter(&mut hasher))?;
+        body.seek(SeekFrom::Start(0))?;
+        let digest = BASE32.encode(&hasher.finalize());
+
+        let mut head = format!("{version:?} {status}\r\n");
+        for (k, v) in headers {
+            if !SKIPPED_HEADERS.contains(k) {
+                head.push_str(&format!(
+                    "{k}: {}\r\n",
+                    String::from_utf8_lossy(v.as_bytes())
+                ));
+            }
+        }
+        head.push_str(&format!("content-length: {len}\r\n\r\n"));
+
+        let date = Timestamp::now();
+        let response_id = record_id();
+        let target = url.split('#').next().unwrap_or(url);
+
+        let mut current = self.current.lock().unwrap();
+        let file = self.file(&mut current)?;
+        let offset = file.file.stream_position()?;
+        let fields = [
+            ("WARC-Type", "response"),
+            ("WARC-Record-ID", &response_id),
+            ("WARC-Date", &warc_date(date)),
+            ("WARC-Target-URI", target),
+            ("WARC-Payload-Digest", &format!("sha1:{digest}")),
+            ("Content-Type", "application/http;msgtype=response"),
+        ];
+        write_record(&mut file.file, &fields, head.as_bytes(), body, len)?;
+        let size = file.file.stream_position()? - offset;
+
+        let (path, host) = path_and_host(target);
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nhost: {host}\r\naccept: */*\r\naccept-encoding: gzip\r\n\r\n"
+        );
+        let fields = [
+            ("WARC-Type", "request"),
+            ("WARC-Record-ID", &record_id()),
+            ("WARC-Date", &warc_date(date)),
+            ("WARC-Target-URI", target),
+            ("WARC-Concurrent-To", &response_id),
+            ("Content-Type", "application/http;msgtype=request"),
+        ];
+        write_record(
+            &mut file.file,
+            &fields,
+            request.as_bytes(),
+            &mut io::empty(),
+            0,
+        )?;
+
+        let mime = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(str::trim)
+            .filter(|m| !m.is_empty() && !m.contains(' '))
+            .unwrap_or("unk");
+        let line = format!(
+            "{} {} {} {mime} {} {digest} - - {size} {offset} {}\n",
+            surt(target),
+            date.strftime("%Y%m%d%H%M%S"),
+            target.replace(' ', "%20"),
+            status.as_u16(),
+            file.name,
+        );
+        self.cdx.lock().unwrap().write_all(line.as_bytes())
+    }
+
+    /// The file to write to, a new one if there is none yet or it is full
+    fn file<'a>(&self, current: &'a mut Option<Current>) -> io::Result<&'a mut Current> {
+        let full = match current.as_mut() {
+            Some(c) => c.file.stream_position()? >= self.max_size,
+            None => true,
+        };
+        if full {
+            let seq = current.as_ref().map_or(0, |c| c.seq + 1);
+            let name = format!("{}-{seq:05}.warc.gz", self.prefix);
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.dir.join(&name))?;
+            let info = format!(
+                "software: rfa/{}\r\nformat: WARC File Format 1.1\r\n",
+                env!("CARGO_PKG_VERSION")
+            );
+            let fields = [
+                ("WARC-Type", "warcinfo"),
+                ("WARC-Record-ID", &record_id()),
+                ("WARC-Date", &warc_date(Timestamp::now())),
+                ("WARC-Filename", &name),
+                ("Content-Type", "application/warc-fields"),
+            ];
+            write_record(&mut file, &fields, info.as_bytes(), &mut io::empty(), 0)?;
+            *current = Some(Current { file, name, seq });
+        }
+        Ok(current.as_mut().unwrap())
+    }
+
+    /// Flush the current file and sort the index, as replay tools expect
+    pub fn finish(&self) -> io::Result<()> {
+        if let Some(c) = self.current.lock().unwrap().as_mut() {
+            c.file.flush()?;
+        }
+        let mut cdx = self.cdx.lock().unwrap();
+        cdx.flush()?;
+        let path = self.dir.join("index.cdx");
+        let mut lines = BufReader::new(File::open(&path)?)
+            .lines()
+            .filter(|l| !l.as_ref().is_ok_and(|l| l.starts_with(" CDX")))
+            .collect::<io::Result<Vec<String>>>()?;
+        lines.sort_unstable();
+        let tmp = path.with_extension("cdx.tmp");
+        let mut out = File::create(&tmp)?;
+        writeln!(out, "{CDX_HEADER}")?;
+        for line in lines {
+            writeln!(out, "{line}")?;
+        }
+        out.sync_all()?;
+        std::fs::rename(&tmp, &path)?;
+        *cdx = OpenOptions::new().append(true).open(&path)?;
+        Ok(())
+    }
+}
+
+/// One record as a gzip member: the WARC header, then `head` and `body` as block
+fn write_record(
+    file: &mut File,
+    fields: &[(&str, &str)],
+    head: &[u8],
+    body: &mut dyn Read,
+    body_len: u64,
+) -> io::Result<()> {
+    let mut gz = GzEncoder::new(file, Compression::default());
+    write!(gz, "WARC/1.1\r\n")?;
+    for (k, v) in fields {
+        write!(gz, "{k}: {v}\r\n")?;
+    }
+    write!(
+        gz,
+        "Content-Length: {}\r\n\r\n",
+        head.len() as u64 + body_len
+    )?;
+    gz.write_all(head)?;
+    io::copy(body, &mut gz)?;
+    gz.write_all(b"\r\n\r\n")?;
+    gz.finish()?;
+    Ok(())
+}
+
+struct HashWriter<'a>(&'a mut Sha1);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `<urn:uuid:…>` of a random (v4) UUID
+fn record_id() -> String {
+    let mut b: [u8; 16] = fastrand::u128(..).to_be_bytes();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn warc_date(ts: Timestamp) -> String {
+    ts.strftime("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Path with query, and host of a url
+fn path_and_host(url: &str) -> (&str, &str) {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    match rest.find('/') {
+        Some(i) => (&rest[i..], &rest[..i]),
+        None => ("/", rest),
+    }
+}
+
+/// Sort-friendly form of a url, as in the CDX `N` field: `org,rfa)/korean/…`
+pub fn surt(url: &str) -> String {
+    let (path, host) = path_and_host(url);
+    let host = host.rsplit('@').next().unwrap_or(host).to_lowercase();
+    let host = host
+        .strip_suffix(":80")
+        .or_else(|| host.strip_suffix(":443"))
+        .unwrap_or(&host);
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let host: Vec<&str> = host.split('.').rev().collect();
+    let path = match path.split_once('?') {
+        Some((path, query)) => {
+            let mut params: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+            params.sort_unstable();
+            format!("{path}?{}", params.join("&"))
+        }
+        None => path.to_owned(),
+    };
+    format!("{}){}", host.join(","), path.to_lowercase())
+}
@@ -0,0 +1,77 @@
+//! Rotation of the proxy pool, against local stand-ins of proxies.
+
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use axum::{Router, http::StatusCode};
+use rfa::proxy::ProxyPool;
+use tokio::net::TcpListener;
+
+/// A proxy answering every request with `status`
+async fn proxy(status: StatusCode) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().fallback(move || async move { (status, "proxied") });
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    format!("http://{addr}")
+}
+
+/// An address nothing listens on
+async fn dead_proxy() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    drop(listener);
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn failing_and_rate_limited_proxies_are_skipped() {
+    let urls = [
+        dead_proxy().await,
+        proxy(StatusCode::TOO_MANY_REQUESTS).await,
+        proxy(StatusCode::OK).await,
+    ];
+    let pool = ProxyPool::new(&urls, reqwest::Client::builder).unwrap();
+
+    let attempts = AtomicUsize::new(0);
+    let resp = pool
+        .get("http://www.example.org/", async || {
+            attempts.fetch_add(1, Ordering::Relaxed);
+        })
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "proxied");
+    assert_eq!(attempts.into_inner(), 3);
+
+    let stats = pool.stats();
+    assert_eq!(stats[0].failures, 1);
+    assert_eq!(stats[1].rate_limited, 1);
+    assert_eq!((stats[2].requests, stats[2].failures), (1, 0));
+    assert_eq!(
+        stats.iter().map(|s| s.healthy).collect::<Vec<_>>(),
+        [false, false, true]
+    );
+
+    // the ones on cooldown are left out
+    for _ in 0..3 {
+        let resp = pool.get("http://www.example.org/", async || {}).await;
+        assert_eq!(resp.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(pool.stats()[2].requests, 4);
+}
+
+#[tokio::test]
+async fn check_puts_dead_proxies_on_cooldown() {
+    let urls = [dead_proxy().await, proxy(StatusCode::OK).await];
+    let pool = ProxyPool::new(&urls, reqwest::Client::builder).unwrap();
+    pool.check("http://www.example.org/robots.txt").await;
+
+    let stats = pool.stats();
+    assert_eq!((stats[0].checks, stats[0].failed_checks), (1, 1));
+    assert!(!stats[0].healthy);
+    assert!(stats[1].healthy);
+    assert_eq!(stats[0].requests, 0);
+}
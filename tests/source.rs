@@ -0,0 +1,221 @@
+//! Backends without ANS: their responses, as small inline fixtures, normalised to
+//! the ANS stories the spider stores.
+
+use jiff::civil::date;
+use reqwest::header::{HeaderMap, HeaderValue};
+use rfa::{
+    site::Site,
+    source::{Query, Rss, Source, WordPress},
+};
+use serde_json::{Value, json};
+
+fn site() -> Site {
+    serde_json::from_value(json!({
+        "id": "news",
+        "prefix": "news",
+        "code": 20,
+        "lang": "en",
+        "name": "News",
+        "logo": "logo-news.png",
+    }))
+    .unwrap()
+}
+
+fn january() -> Query {
+    Query {
+        begin: date(2020, 1, 1),
+        end: date(2020, 1, 31),
+        offset: 0,
+        size: 100,
+    }
+}
+
+fn types(story: &Value) -> Vec<&str> {
+    story["content_elements"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["type"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn wordpress_posts_become_ans() {
+    let wp = WordPress {
+        base: "https://example.org".to_owned(),
+    };
+    let posts = json!([{
+        "id": 42,
+        "link": "https://example.org/2020/01/a-post/",
+        "date_gmt": "2020-01-05T08:30:00",
+        "modified_gmt": "2020-01-06T10:00:00",
+        "title": { "rendered": "Rock &amp; <em>roll</em>" },
+        "excerpt": { "rendered": "<p>An excerpt&#8230;</p>" },
+        "content": { "rendered": "<h2>Intro</h2><p>First <b>paragraph</b></p><figure><img src=\"https://example.org/a.jpg\"><figcaption>A caption</figcaption></figure>" },
+        "_embedded": {
+            "author": [{
+                "name": "Jane Doe",
+                "slug": "jane",
+                "link": "https://example.org/author/jane/",
+                "avatar_urls": { "24": "https://example.org/s.jpg", "96": "https://example.org/l.jpg" },
+            }],
+            "wp:featuredmedia": [{
+                "source_url": "https://example.org/featured.jpg",
+                "caption": { "rendered": "<p>Featured</p>" },
+            }],
+            "wp:term": [[{ "slug": "world", "name": "World" }]],
+        },
+    }]);
+    let mut headers = HeaderMap::new();
+    headers.insert("x-wp-total", HeaderValue::from_static("101"));
+
+    let listing = wp
+        .parse_list(&site(), &january(), &headers, posts.to_string().as_bytes())
+        .unwrap();
+    assert_eq!(listing.count, Some(101));
+    assert_eq!(listing.next, Some(1));
+    let story = &listing.stories[0];
+    assert_eq!(story["_id"], "42");
+    assert_eq!(story["display_date"], "2020-01-05T08:30:00Z");
+    assert_eq!(story["last_updated_date"], "2020-01-06T10:00:00Z");
+    assert_eq!(story["headlines"]["basic"], "Rock & roll");
+    assert_eq!(story["description"]["basic"], "An excerpt…");
+    let website = &story["websites"]["news"];
+    assert_eq!(website["website_url"], "/news/2020/01/a-post");
+    assert_eq!(website["website_section"]["_id"], "/news/world");
+    assert_eq!(story["credits"]["by"][0]["name"], "Jane Doe");
+    assert_eq!(
+        story["credits"]["by"][0]["image"]["url"],
+        "https://example.org/l.jpg"
+    );
+    assert_eq!(
+        story["promo_items"]["basic"]["url"],
+        "https://example.org/featured.jpg"
+    );
+    assert_eq!(types(story), ["header", "text", "image"]);
+    assert_eq!(
+        story["content_elements"][1]["content"],
+        "First <b>paragraph</b>"
+    );
+    assert_eq!(story["content_elements"][2]["caption"], "A caption");
+}
+
+#[test]
+fn feed_items_of_the_window_become_ans() {
+    let rss = Rss {
+        feed: "https://example.org/feed".to_owned(),
+    };
+    let feed = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel>
+  <title>News</title>
+  <item>
+    <title>Fish &amp; chips &#x2014; <![CDATA[<i>again</i>]]></title>
+    <link>https://example.org/news/fish</link>
+    <guid>fish-1</guid>
+    <pubDate>Tue, 14 Jan 2020 09:00:00 +0000</pubDate>
+    <dc:creator>John Roe</dc:creator>
+    <category>Food News</category>
+    <description>Short &lt;b&gt;text&lt;/b&gt;</description>
+    <content:encoded><![CDATA[<p>Body</p><img src="https://example.org/in.jpg" alt="Inline"><ul><li>a</li></ul><script>x()</script>]]></content:encoded>
+    <enclosure url="https://example.org/cover.jpg" type="image/jpeg" length="1"/>
+    <enclosure url="https://example.org/show.mp3" type="audio/mpeg" length="1"/>
+  </item>
+  <item>
+    <title>Last year</title>
+    <link>https://example.org/news/old</link>
+    <pubDate>Mon, 30 Dec 2019 09:00:00 +0000</pubDate>
+  </item>
+</channel>
+</rss>"#;
+
+    let listing = rss
+        .parse_list(&site(), &january(), &HeaderMap::new(), feed.as_bytes())
+        .unwrap();
+    assert_eq!(listing.count, Some(1));
+    assert_eq!(listing.next, None);
+    let story = &listing.stories[0];
+    assert_eq!(story["_id"], "fish-1");
+    assert_eq!(story["display_date"], "2020-01-14T09:00:00Z");
+    assert_eq!(story["headlines"]["basic"], "Fish & chips — again");
+    assert_eq!(story["description"]["basic"], "Short text");
+    assert_eq!(story["credits"]["by"][0]["name"], "John Roe");
+    let website = &story["websites"]["news"];
+    assert_eq!(website["website_url"], "/news/fish");
+    assert_eq!(website["website_section"]["_id"], "/news/food-news");
+    assert_eq!(website["website_section"]["name"], "Food News");
+    assert_eq!(
+        story["promo_items"]["basic"]["url"],
+        "https://example.org/cover.jpg"
+    );
+    let lead_art = &story["promo_items"]["lead_art"];
+    assert_eq!(lead_art["type"], "audio");
+    assert_eq!(
+        lead_art["streams"][0]["url"],
+        "https://example.org/show.mp3"
+    );
+    assert_eq!(types(story), ["text", "image", "text"]);
+    assert_eq!(story["content_elements"][1]["caption"], "Inline");
+    assert_eq!(
+        story["content_elements"][2]["content"],
+        "<ul><li>a</li></ul>"
+    );
+}
+
+#[test]
+fn feed_items_without_a_link_are_skipped() {
+    let rss = Rss {
+        feed: "https://example.org/feed".to_owned(),
+    };
+    let feed = "<rss><channel><item><title>No link</title><pubDate>Tue, 14 Jan 2020 09:00:00 +0000</pubDate></item></channel></rss>";
+    let listing = rss
+        .parse_list(&site(), &january(), &HeaderMap::new(), feed.as_bytes())
+        .unwrap();
+    assert_eq!(listing.count, Some(1));
+    assert!(listing.stories.is_empty());
+}
+
+#[test]
+fn wordpress_posts_without_an_id_are_skipped() {
+    let wp = WordPress {
+        base: "https://example.org".to_owned(),
+    };
+    let posts = json!([
+        { "link": "https://example.org/2020/01/no-id/", "date_gmt": "2020-01-05T08:30:00" },
+        { "id": 43, "link": "https://example.org/2020/01/b-post/", "date_gmt": "2020-01-06T08:30:00" },
+    ]);
+    let listing = wp
+        .parse_list(
+            &site(),
+            &january(),
+            &HeaderMap::new(),
+            posts.to_string().as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(listing.stories.len(), 1);
+    assert_eq!(listing.stories[0]["_id"], "43");
+}
+
+#[test]
+fn odd_html_does_not_end_the_post() {
+    let wp = WordPress {
+        base: "https://example.org".to_owned(),
+    };
+    let posts = json!([{
+        "id": 44,
+        "link": "https://example.org/2020/01/odd/",
+        "date_gmt": "2020-01-07T08:30:00",
+        "content": { "rendered": "<p title=\"</p>\">Quoted</p><div/><h2>Empty</h2><p>Last</p>" },
+    }]);
+    let listing = wp
+        .parse_list(
+            &site(),
+            &january(),
+            &HeaderMap::new(),
+            posts.to_string().as_bytes(),
+        )
+        .unwrap();
+    let story = &listing.stories[0];
+    assert_eq!(types(story), ["text", "header", "text"]);
+    assert_eq!(story["content_elements"][2]["content"], "Last");
+}
@@ -0,0 +1,1166 @@
+//! End-to-end runs of `spider` against the replay server, without network.
+//!
+//! Fixtures are written like `--record` does, for a month of `rfa-korean` with
+//! 150 stories over two pages.
+
+use std::{
+    io::Read,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use fjall::{Config, Keyspace, PartitionCreateOptions, PartitionHandle};
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use jiff::civil::date;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
+use rfa::{
+    author::{Author, byline_prefix, byline_url},
+    compress::{Codec, frame_dict_id},
+    kv_sep_partition_option, replay,
+    report::RunReport,
+    section::Section,
+    site::Sites,
+    source::{ArcXp, Image, Query, Source, StoryFilter, StoryRef},
+};
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+
+const SITE: &str = "rfa-korean";
+
+/// 1x1 PNG
+const PNG: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4,
+    0x89, 0x00, 0x00, 0x00, 0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x00, 0x01, 0x00, 0x00,
+    0x05, 0x00, 0x01, 0x0d, 0x0a, 0x2d, 0xb4, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+    0x42, 0x60, 0x82,
+];
+
+/// `PNG` claiming another size, enough for the header checks
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut png = PNG.to_vec();
+    png[16..20].copy_from_slice(&width.to_be_bytes());
+    png[20..24].copy_from_slice(&height.to_be_bytes());
+    png
+}
+
+fn img_url(i: usize) -> String {
+    format!("https://www.rfa.org/resizer/v2/img-{}.png", i % 5)
+}
+
+fn story(i: usize) -> Value {
+    json!({
+        "_id": format!("story{i}"),
+        "type": "story",
+        "display_date": format!("2020-01-{:02}T08:00:00.000Z", i % 28 + 1),
+        "headlines": { "basic": format!("Story {i}") },
+        "credits": { "by": [credit(i)] },
+        "promo_items": { "basic": { "type": "image", "url": img_url(i) } },
+        "websites": {
+            SITE: {
+                "website_section": { "_id": "/korean/news", "name": "News" },
+                "website_url": format!("/korean/news/story-{i}.html"),
+            }
+        },
+        "content_elements": [{ "type": "text", "content": format!("Text of story {i}") }],
+    })
+}
+
+/// Even stories by a profiled author, with a photo, odd ones by a plain byline
+fn credit(i: usize) -> Value {
+    if i.is_multiple_of(2) {
+        json!({
+            "type": "author",
+            "name": "Jane Doe",
+            "url": "/author/jane-doe/",
+            "description": "Reporter",
+            "image": { "url": img_url(0) },
+        })
+    } else {
+        json!({ "type": "reference", "name": "김 철수" })
+    }
+}
+
+fn page_url(offset: u64) -> String {
+    page_url_of(&ArcXp::default(), offset)
+}
+
+fn page_url_of(source: &ArcXp, offset: u64) -> String {
+    let sites = Sites::builtin();
+    let query = Query {
+        begin: date(2020, 1, 1),
+        end: date(2020, 1, 31),
+        offset,
+        size: 100,
+    };
+    source.list_url(sites.by_id(SITE).unwrap(), &query)
+}
+
+fn save(dir: &Path, url: &str, content_type: &'static str, body: &[u8]) {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    replay::save(dir, url, 200, &headers, body).unwrap();
+}
+
+/// First page: stories 0 to 99, of 150
+fn save_page1(dir: &Path) {
+    let page = json!({
+        "content_elements": (0..100).map(story).collect::<Vec<_>>(),
+        "count": 150,
+        "next": 100,
+    });
+    save(
+        dir,
+        &page_url(0),
+        "application/json",
+        page.to_string().as_bytes(),
+    );
+}
+
+/// Second page: stories 100 to 149, and story 99 again, as a shifting result set does
+fn save_page2(dir: &Path) {
+    let page = json!({
+        "content_elements": (99..150).map(story).collect::<Vec<_>>(),
+        "count": 150,
+    });
+    save(
+        dir,
+        &page_url(100),
+        "application/json",
+        page.to_string().as_bytes(),
+    );
+}
+
+/// The site service hierarchy: the root, News with a link and North Korea
+fn save_sections(dir: &Path) {
+    let sites = Sites::builtin();
+    let url = ArcXp::default()
+        .sections_url(sites.by_id(SITE).unwrap())
+        .unwrap();
+    let hierarchy = json!({
+        "_id": "/korean",
+        "name": "Korean",
+        "children": [{
+            "_id": "/korean/news",
+            "name": "News",
+            "order": { "default": 1 },
+            "site": { "site_description": "Latest news" },
+            "children": [
+                { "_id": "link-1", "node_type": "link", "url": "https://example.com" },
+                { "_id": "/korean/news/nk", "name": "North Korea", "site": { "site_url": "/korean/news/nk/" } },
+            ],
+        }],
+    });
+    save(
+        dir,
+        &url,
+        "application/json",
+        hierarchy.to_string().as_bytes(),
+    );
+}
+
+fn save_imgs(dir: &Path) {
+    for i in 0..5 {
+        save(dir, &img_url(i), "image/png", PNG);
+    }
+}
+
+/// Replay `dir` on a free port
+async fn replay_server(dir: &Path) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(replay::serve(dir.to_owned(), listener));
+    addr
+}
+
+async fn spider(output: &Path, addr: SocketAddr, extra: &[&str]) {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spider"));
+    cmd.args(["-w", SITE, "--from", "2020-01", "--to", "2020-01", "-o"])
+        .arg(output)
+        .args(["--replay", &addr.to_string()])
+        .args(extra);
+    let output = tokio::task::spawn_blocking(move || cmd.output().unwrap())
+        .await
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+struct Db {
+    _keyspace: Keyspace,
+    rfa: PartitionHandle,
+    index: PartitionHandle,
+    done: PartitionHandle,
+    progress: PartitionHandle,
+    blobs: PartitionHandle,
+    replaced: PartitionHandle,
+    runs: PartitionHandle,
+    queue: PartitionHandle,
+    sections: PartitionHandle,
+    authors: PartitionHandle,
+    bylines: PartitionHandle,
+    images: PartitionHandle,
+    failed: PartitionHandle,
+    revisions: PartitionHandle,
+    codec: Codec,
+}
+
+/// Open the archive written by `spider`, drop it before running `spider` again
+fn open(output: &Path) -> Db {
+    let keyspace = Config::new(output.join("rfa.db")).open().unwrap();
+    let partition = |name| {
+        keyspace
+            .open_partition(name, PartitionCreateOptions::default())
+            .unwrap()
+    };
+    Db {
+        rfa: keyspace
+            .open_partition("rfa", kv_sep_partition_option())
+            .unwrap(),
+        index: partition("index"),
+        done: partition("done"),
+        progress: partition("progress"),
+        blobs: partition("blobs"),
+        replaced: partition("replaced"),
+        runs: partition("runs"),
+        queue: partition("queue"),
+        sections: partition("sections"),
+        authors: partition("authors"),
+        bylines: partition("bylines"),
+        images: partition("images"),
+        failed: partition("failed"),
+        revisions: keyspace
+            .open_partition("revisions", kv_sep_partition_option())
+            .unwrap(),
+        codec: Codec::new(partition("dicts")),
+        _keyspace: keyspace,
+    }
+}
+
+fn last_run(db: &Db) -> RunReport {
+    RunReport::load_all(&db.runs).pop().unwrap()
+}
+
+fn fixtures() -> (TempDir, PathBuf, PathBuf) {
+    let tmp = TempDir::new().unwrap();
+    let fixtures = tmp.path().join("fixtures");
+    let output = tmp.path().join("out");
+    std::fs::create_dir(&fixtures).unwrap();
+    (tmp, fixtures, output)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn crawl_paginates_downloads_and_marks_done() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    spider(&output, addr, &[]).await;
+    {
+        let db = open(&output);
+        assert_eq!(db.rfa.len().unwrap(), 150);
+        assert_eq!(db.index.len().unwrap(), 150);
+        assert!(db.rfa.contains_key("korean/news/story-149.html").unwrap());
+        assert!(db.done.contains_key(format!("{SITE}-2020-1")).unwrap());
+        assert!(db.progress.is_empty().unwrap());
+
+        assert_eq!(db.blobs.len().unwrap(), 5);
+        let meta: Value =
+            serde_json::from_slice(&db.images.get(img_url(0)).unwrap().unwrap()).unwrap();
+        assert_eq!(meta["width"], 1);
+        assert_eq!(meta["src"], img_url(0));
+        for kv in db.blobs.iter() {
+            let (_, path) = kv.unwrap();
+            let path = output.join(String::from_utf8_lossy(&path).as_ref());
+            assert_eq!(std::fs::read(path).unwrap(), PNG);
+        }
+
+        let run = last_run(&db);
+        let window = &run.windows[0];
+        assert_eq!(window.count, 150);
+        assert_eq!(window.fetched, 150);
+        assert_eq!(window.stored, 150);
+        assert_eq!(window.imgs.downloaded, 5);
+        assert!(window.complete);
+        assert!(window.errors.is_empty());
+
+        let sections = Section::load_all(&db.sections, SITE);
+        assert_eq!(sections.len(), 3);
+        let crumbs = Section::breadcrumbs(&db.sections, SITE, "/korean/news/nk");
+        let names: Vec<_> = crumbs.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["Korean", "News", "North Korea"]);
+        assert_eq!(crumbs[1].description.as_deref(), Some("Latest news"));
+        assert_eq!(crumbs[2].url, "/korean/news/nk");
+
+        assert_eq!(db.authors.len().unwrap(), 2);
+        let jane = Author::load(&db.authors, SITE, "jane-doe").unwrap();
+        assert_eq!(jane.bio.as_deref(), Some("Reporter"));
+        assert_eq!(jane.site, SITE);
+        assert!(db.blobs.contains_key(jane.photo.unwrap()).unwrap());
+        // authors of each site are kept apart
+        assert!(Author::load(&db.authors, "rfa-mandarin", "jane-doe").is_none());
+        let bylines = db.bylines.prefix(byline_prefix(SITE, "jane-doe"));
+        let urls: Vec<_> = bylines
+            .map(|kv| byline_url(&kv.unwrap().0).unwrap())
+            .collect();
+        assert_eq!(urls.len(), 75);
+        assert!(urls.contains(&"korean/news/story-148.html".to_owned()));
+        assert_eq!(
+            db.bylines.prefix(byline_prefix(SITE, "김-철수")).count(),
+            75
+        );
+    }
+
+    // a done month is skipped, an empty server would fail any request
+    let empty = fixtures.with_file_name("empty");
+    std::fs::create_dir(&empty).unwrap();
+    let addr = replay_server(&empty).await;
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert!(last_run(&db).windows.is_empty());
+    assert_eq!(db.rfa.len().unwrap(), 150);
+    // and the sections are kept when their refresh fails
+    assert_eq!(Section::load_all(&db.sections, SITE).len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupted_month_resumes_from_its_cursor() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    // the second page is missing
+    spider(&output, addr, &[]).await;
+    {
+        let db = open(&output);
+        assert_eq!(db.rfa.len().unwrap(), 100);
+        assert!(!db.done.contains_key(format!("{SITE}-2020-1")).unwrap());
+        let offset = db.progress.get(format!("{SITE}-2020-1")).unwrap().unwrap();
+        assert_eq!(u64::from_be_bytes(offset[..].try_into().unwrap()), 100);
+
+        let window = &last_run(&db).windows[0];
+        assert!(!window.complete);
+        assert_eq!(window.errors.len(), 1);
+    }
+
+    // the first page is no longer needed
+    std::fs::remove_dir_all(&fixtures).unwrap();
+    std::fs::create_dir(&fixtures).unwrap();
+    save_page2(&fixtures);
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert_eq!(db.rfa.len().unwrap(), 150);
+    assert!(db.done.contains_key(format!("{SITE}-2020-1")).unwrap());
+    assert!(db.progress.is_empty().unwrap());
+    let window = &last_run(&db).windows[0];
+    assert!(window.complete);
+    // story 99 is seen again, but unchanged
+    assert_eq!(window.fetched, 51);
+    assert_eq!(window.stored, 50);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn record_saves_replayable_fixtures() {
+    let (tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    let recorded = tmp.path().join("recorded");
+    spider(&output, addr, &["--record", recorded.to_str().unwrap()]).await;
+
+    // 2 pages, the sections and 5 images, a metadata and a body file each
+    assert_eq!(std::fs::read_dir(&recorded).unwrap().count(), 16);
+    for url in [page_url(0), page_url(100), img_url(0)] {
+        let (fixture, body) = replay::load(&recorded, &url).unwrap().unwrap();
+        let (_, expected) = replay::load(&fixtures, &url).unwrap().unwrap();
+        assert_eq!(fixture.status, 200);
+        assert_eq!(body, expected);
+    }
+
+    // the recording alone replays the crawl
+    let output = tmp.path().join("out2");
+    let addr = replay_server(&recorded).await;
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert_eq!(db.rfa.len().unwrap(), 150);
+    assert_eq!(db.blobs.len().unwrap(), 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discover_queues_and_fetches_missing_stories() {
+    let (_tmp, fixtures, output) = fixtures();
+    let sites = Sites::builtin();
+    let site = sites.by_id(SITE).unwrap();
+    let [index, feed] = &site.discovery_urls()[..] else {
+        panic!("Arc sites have a sitemap index and a feed");
+    };
+
+    let sitemap = "https://www.rfa.org/arc/outboundfeeds/sitemap/2020-01/";
+    let sitemap_index = format!(
+        r#"<?xml version="1.0"?><sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"><sitemap><loc>{sitemap}</loc></sitemap></sitemapindex>"#
+    );
+    save(
+        &fixtures,
+        index,
+        "application/xml",
+        sitemap_index.as_bytes(),
+    );
+    // a section page, a story of another site and a back-dated story
+    let urlset = r#"<?xml version="1.0"?><urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+        <url><loc>https://www.rfa.org/korean/news/</loc></url>
+        <url><loc>https://www.rfa.org/mandarin/news/story-7.html</loc></url>
+        <url><loc>https://www.rfa.org/korean/news/story-7.html</loc><lastmod>2020-01-08T08:00:00Z</lastmod></url>
+    </urlset>"#;
+    save(&fixtures, sitemap, "application/xml", urlset.as_bytes());
+    let rss = r#"<rss><channel><item><link>https://www.rfa.org/korean/news/story-7.html</link></item></channel></rss>"#;
+    save(&fixtures, feed, "application/rss+xml", rss.as_bytes());
+
+    let path = "/korean/news/story-7.html";
+    let story_url = ArcXp::default()
+        .story_url(site, StoryRef::Path(path))
+        .unwrap();
+    save(
+        &fixtures,
+        &story_url,
+        "application/json",
+        story(7).to_string().as_bytes(),
+    );
+    save(&fixtures, &img_url(7), "image/png", PNG);
+
+    let addr = replay_server(&fixtures).await;
+    spider(&output, addr, &["discover"]).await;
+    {
+        let db = open(&output);
+        assert_eq!(db.queue.len().unwrap(), 1);
+        assert!(
+            db.queue
+                .contains_key("https://www.rfa.org/korean/news/story-7.html")
+                .unwrap()
+        );
+    }
+
+    spider(&output, addr, &["fetch", "--queue"]).await;
+    let db = open(&output);
+    assert!(db.queue.is_empty().unwrap());
+    assert!(db.rfa.contains_key(path.trim_start_matches('/')).unwrap());
+    assert_eq!(db.blobs.len().unwrap(), 1);
+}
+
+#[test]
+fn images_resolve_to_their_original() {
+    let element = json!({
+        "_id": "ABC",
+        "auth": { "1": "t0k" },
+        "url": "https://www.rfa.org/resizer/v2/ABC.png?auth=old&width=200&height=100",
+        "width": 4000,
+        "height": 3000,
+        "credits": { "by": [{ "name": "Jane Doe" }], "affiliation": [{ "name": "AFP" }] },
+    });
+    let img = Image::from_ans(&element, "www.rfa.org").unwrap();
+    assert_eq!(img.url, element["url"]);
+    assert_eq!(img.src, "https://www.rfa.org/resizer/v2/ABC.png?auth=t0k");
+    assert_eq!((img.width, img.height), (Some(4000), Some(3000)));
+    assert_eq!(img.credit.as_deref(), Some("Jane Doe, AFP"));
+
+    // without a token, the resizer url loses its size only
+    let thumb = Image::plain("/resizer/v2/ABC.png?auth=old&width=200", "www.rfa.org");
+    assert_eq!(thumb.src, "https://www.rfa.org/resizer/v2/ABC.png?auth=old");
+    let relative = Image::plain("korean/img.jpg", "www.rfa.org");
+    assert_eq!(relative.src, "https://www.rfa.org/korean/img.jpg");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn archived_thumbnails_are_replaced_by_their_original() {
+    let (_tmp, fixtures, output) = fixtures();
+    let sites = Sites::builtin();
+    let path = "/korean/news/story-0.html";
+    let story_url = ArcXp::default()
+        .story_url(sites.by_id(SITE).unwrap(), StoryRef::Path(path))
+        .unwrap();
+    let url = format!("https://www.rfa.org{path}");
+    save(
+        &fixtures,
+        &story_url,
+        "application/json",
+        story(0).to_string().as_bytes(),
+    );
+    save(&fixtures, &img_url(0), "image/png", PNG);
+    let addr = replay_server(&fixtures).await;
+    spider(&output, addr, &["fetch", &url]).await;
+    let thumbnail = {
+        let db = open(&output);
+        let blob = db.blobs.get(img_url(0)).unwrap().unwrap();
+        output.join(String::from_utf8_lossy(&blob).as_ref())
+    };
+
+    // the story now tells the size of the original and how to get it
+    let mut upgraded = story(0);
+    upgraded["promo_items"]["basic"] = json!({
+        "_id": "img-0",
+        "auth": { "1": "t0k" },
+        "type": "image",
+        "url": img_url(0),
+        "width": 2,
+        "height": 2,
+    });
+    save(
+        &fixtures,
+        &story_url,
+        "application/json",
+        upgraded.to_string().as_bytes(),
+    );
+    let original = "https://www.rfa.org/resizer/v2/img-0.png?auth=t0k";
+    save(&fixtures, original, "image/png", &png(2, 2));
+    spider(&output, addr, &["fetch", &url]).await;
+    {
+        let db = open(&output);
+        let blob = db.blobs.get(img_url(0)).unwrap().unwrap();
+        let blob = output.join(String::from_utf8_lossy(&blob).as_ref());
+        assert_eq!(std::fs::read(blob).unwrap(), png(2, 2));
+        let meta: Value =
+            serde_json::from_slice(&db.images.get(img_url(0)).unwrap().unwrap()).unwrap();
+        assert_eq!(meta["src"], original);
+        assert_eq!(meta["width"], 2);
+    }
+    // no other url refers to the thumbnail, so repairing deletes it
+    assert!(thumbnail.exists());
+    spider(&output, addr, &["verify", "--repair"]).await;
+    assert!(!thumbnail.exists());
+    assert!(open(&output).replaced.is_empty().unwrap());
+
+    // the copy from the original's source is kept, an empty server would fail it
+    let empty = fixtures.with_file_name("empty");
+    std::fs::create_dir(&empty).unwrap();
+    save(
+        &empty,
+        &story_url,
+        "application/json",
+        upgraded.to_string().as_bytes(),
+    );
+    let addr = replay_server(&empty).await;
+    spider(&output, addr, &["fetch", &url]).await;
+    assert!(open(&output).failed.is_empty().unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warc_captures_exchanges_with_a_cdx_index() {
+    let (tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    // rotate after every exchange
+    let warc = tmp.path().join("warc");
+    let args = ["--warc", warc.to_str().unwrap(), "--warc-max-mb", "0"];
+    spider(&output, addr, &args).await;
+
+    // 2 pages, the sections and 5 images
+    let mut files: Vec<_> = std::fs::read_dir(&warc)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".warc.gz"))
+        .collect();
+    files.sort();
+    assert_eq!(files.len(), 8);
+    let mut all = String::new();
+    for file in &files {
+        let gz = std::fs::read(warc.join(file)).unwrap();
+        let mut records = vec![];
+        MultiGzDecoder::new(&gz[..])
+            .read_to_end(&mut records)
+            .unwrap();
+        all.push_str(&String::from_utf8_lossy(&records));
+    }
+    assert_eq!(all.matches("WARC-Type: warcinfo").count(), 8);
+    assert_eq!(all.matches("WARC-Type: response").count(), 8);
+    assert_eq!(all.matches("WARC-Type: request").count(), 8);
+
+    let cdx = std::fs::read_to_string(warc.join("index.cdx")).unwrap();
+    let mut lines = cdx.lines();
+    assert_eq!(lines.next(), Some(" CDX N b a m s k r M S V g"));
+    let lines: Vec<&str> = lines.collect();
+    assert_eq!(lines.len(), 8);
+    assert!(lines.is_sorted());
+
+    // the index points at the response record of each url
+    let page = page_url(100);
+    let line = lines
+        .iter()
+        .map(|l| l.split(' ').collect::<Vec<_>>())
+        .find(|f| f[2] == page)
+        .unwrap();
+    assert!(line[0].starts_with("org,rfa)/pf/api/v3/content/fetch/"));
+    assert_eq!((line[3], line[4]), ("application/json", "200"));
+    let (size, offset): (usize, usize) = (line[8].parse().unwrap(), line[9].parse().unwrap());
+    let gz = std::fs::read(warc.join(line[10])).unwrap();
+    let mut record = String::new();
+    GzDecoder::new(&gz[offset..offset + size])
+        .read_to_string(&mut record)
+        .unwrap();
+    assert!(record.starts_with("WARC/1.1\r\nWARC-Type: response\r\n"));
+    assert!(record.contains(&format!("WARC-Target-URI: {page}\r\n")));
+    assert!(record.contains("HTTP/1.1 200 OK\r\n"));
+    let (_, body) = record.split_once("\r\n\r\n").unwrap();
+    let (_, body) = body.split_once("\r\n\r\n").unwrap();
+    let page: Value = serde_json::from_str(body.trim_end()).unwrap();
+    assert_eq!(page["content_elements"].as_array().unwrap().len(), 51);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compress_converts_plain_stories_with_a_site_dictionary() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+    spider(&output, addr, &[]).await;
+
+    let mut stories = vec![];
+    {
+        let db = open(&output);
+        // compressed without a dictionary until one is trained
+        assert_eq!(db.codec.dict_id(SITE), None);
+        let v = db.rfa.get("korean/news/story-7.html").unwrap().unwrap();
+        assert!(v.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+
+        // as an archive from before compression
+        for kv in db.rfa.iter() {
+            let (k, v) = kv.unwrap();
+            let json = db.codec.decode(&v).unwrap().into_owned();
+            stories.push((k.to_vec(), serde_json::from_slice::<Value>(&json).unwrap()));
+            db.rfa.insert(k, json).unwrap();
+        }
+    }
+
+    spider(&output, addr, &["compress"]).await;
+    let db = open(&output);
+    let id = db.codec.dict_id(SITE).unwrap();
+    assert_eq!(stories.len(), 150);
+    for (k, json) in stories {
+        let v = db.rfa.get(&k).unwrap().unwrap();
+        assert_eq!(frame_dict_id(&v), Some(id));
+        assert_eq!(db.codec.value(&v).unwrap(), json);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn full_stories_are_stored_whole() {
+    let (_tmp, fixtures, output) = fixtures();
+    let full = ArcXp {
+        filter: StoryFilter::Full,
+        ..Default::default()
+    };
+    let url = page_url_of(&full, 0);
+    assert!(!url.contains("filter="));
+    let mut stories: Vec<Value> = (0..3).map(story).collect();
+    for story in &mut stories {
+        story["taxonomy"] = json!({ "tags": [{ "slug": "north-korea" }] });
+        story["subheadlines"] = json!({ "basic": "Subheadline" });
+    }
+    let page = json!({ "content_elements": stories, "count": 3 });
+    save(
+        &fixtures,
+        &url,
+        "application/json",
+        page.to_string().as_bytes(),
+    );
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    spider(&output, addr, &["--full-stories"]).await;
+    let db = open(&output);
+    let v = db.rfa.get("korean/news/story-1.html").unwrap().unwrap();
+    let json = db.codec.value(&v).unwrap();
+    assert_eq!(json["taxonomy"]["tags"][0]["slug"], "north-korea");
+    assert_eq!(json["subheadlines"]["basic"], "Subheadline");
+}
+
+#[test]
+fn custom_filters_name_the_website() {
+    let custom = ArcXp {
+        filter: StoryFilter::Custom("_id,\n  websites{$site{website_url}}\n".to_owned()),
+        ..Default::default()
+    };
+    let sites = Sites::builtin();
+    let url = custom
+        .story_url(sites.by_id(SITE).unwrap(), StoryRef::Id("story1"))
+        .unwrap();
+    let filter = url
+        .split("&filter=")
+        .nth(1)
+        .unwrap()
+        .split('&')
+        .next()
+        .unwrap();
+    assert_eq!(
+        urlencoding::decode(filter).unwrap(),
+        "{_id,websites{rfa-korean{website_url}}}"
+    );
+}
+
+#[test]
+fn custom_filters_keep_the_stored_fields() {
+    let check = |fields: &str| StoryFilter::Custom(fields.to_owned()).check();
+    assert!(check("_id, display_date,\n websites{ $site{website_url, website_section} }").is_ok());
+    assert!(check("_id,websites{$site{website_url}}").is_err());
+    assert!(check("_id,display_date,websites{rfa-korean{website_url}}").is_err());
+    assert!(check("_id,display_date,websites{$site{website_section}},website_url").is_err());
+
+    let (_tmp, fixtures, output) = fixtures();
+    let filter = fixtures.join("filter.txt");
+    std::fs::write(&filter, "_id,websites{$site{website_url}}").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_spider"))
+        .arg("-o")
+        .arg(&output)
+        .arg("--filter-file")
+        .arg(&filter)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("lacks display_date"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn daemon_backs_off_failing_refreshes_and_backfills() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    // the fixtures have no recent stories, so every refresh fails
+    let mut child = Command::new(env!("CARGO_BIN_EXE_spider"))
+        .args(["-w", SITE, "--from", "2020-01", "--to", "2020-01", "-o"])
+        .arg(&output)
+        .args(["--replay", &addr.to_string()])
+        .args(["daemon", "--refresh-every", "2h", "--backfill-every", "1d"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let path = output.join("daemon.json");
+    let mut status = Value::Null;
+    for _ in 0..300 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        if let Ok(v) = std::fs::read(&path) {
+            status = serde_json::from_slice(&v).unwrap();
+            let finished = |task: &str| {
+                status[task]["last_finished"].is_string() && status[task]["running"] == false
+            };
+            if finished("refresh") && finished("backfill") {
+                break;
+            }
+        }
+    }
+    let stopped = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(stopped.success());
+    let exit = tokio::task::spawn_blocking(move || child.wait().unwrap())
+        .await
+        .unwrap();
+    assert!(exit.success());
+
+    let refresh = &status["refresh"];
+    assert_eq!(
+        (refresh["runs"].as_u64(), refresh["failures"].as_u64()),
+        (Some(1), Some(1))
+    );
+    assert!(
+        refresh["last_error"]
+            .as_str()
+            .unwrap()
+            .starts_with("all 1 windows failed")
+    );
+    assert!(refresh["last_success"].is_null());
+    assert_eq!(refresh["interval_secs"], 7200);
+
+    let backfill = &status["backfill"];
+    assert_eq!(
+        (backfill["runs"].as_u64(), backfill["failures"].as_u64()),
+        (Some(1), Some(0))
+    );
+    assert!(backfill["last_success"].is_string());
+    assert_eq!(backfill["summary"]["totals"]["stored"], 150);
+    assert_eq!(backfill["summary"]["verify"]["articles"], 150);
+    assert_eq!(backfill["summary"]["verify"]["missing_index"], 0);
+
+    // the refresh did not wait for the backfill
+    let time = |task: &str, field: &str| -> jiff::Timestamp {
+        status[task][field].as_str().unwrap().parse().unwrap()
+    };
+    assert!(time("backfill", "last_started") < time("refresh", "last_finished"));
+
+    let db = open(&output);
+    let mut runs = RunReport::load_all(&db.runs);
+    runs.sort_by(|a, b| a.mode.cmp(&b.mode));
+    assert_eq!(
+        runs.iter().map(|r| r.mode.as_str()).collect::<Vec<_>>(),
+        ["crawl", "refresh"]
+    );
+    assert_eq!(backfill["last_run"], runs[0].id);
+    assert_eq!(refresh["last_run"], runs[1].id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn every_edit_keeps_a_revision() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+    spider(&output, addr, &[]).await;
+
+    // an edit outside the text, then one of the headline, both without `last_updated_date`
+    let edits: [fn(&mut Value); 2] = [
+        |story| story["promo_items"]["basic"]["url"] = json!(img_url(1)),
+        |story| story["headlines"]["basic"] = json!("Story 0, updated"),
+    ];
+    let mut stories: Vec<Value> = (0..100).map(story).collect();
+    for edit in edits {
+        edit(&mut stories[0]);
+        let page = json!({ "content_elements": stories, "count": 150, "next": 100 });
+        save(
+            &fixtures,
+            &page_url(0),
+            "application/json",
+            page.to_string().as_bytes(),
+        );
+        spider(&output, addr, &["--recrawl"]).await;
+    }
+
+    let db = open(&output);
+    let revisions: Vec<Value> = db
+        .revisions
+        .prefix("korean/news/story-0.html\0")
+        .map(|kv| db.codec.value(&kv.unwrap().1).unwrap())
+        .collect();
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[0], story(0));
+    assert_eq!(revisions[1]["headlines"]["basic"], "Story 0");
+    assert_eq!(revisions[1]["promo_items"]["basic"]["url"], img_url(1));
+    assert_eq!(db.revisions.len().unwrap(), 2);
+}
+
+#[test]
+fn empty_or_conflicting_windows_are_rejected() {
+    for args in [
+        &["--from", "2020-02", "--to", "2020-01"][..],
+        &["--from", "2099-01"],
+        &["--refresh-days", "3", "--from", "2020-01"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_spider"))
+            .args(["-o", "/nonexistent"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--from"), "{stderr}");
+    }
+}
+
+#[test]
+fn a_broken_site_registry_is_reported() {
+    let (_tmp, fixtures, output) = fixtures();
+    let config = fixtures.join("sites.json");
+    std::fs::write(&config, "[{\"id\": \"rfa-korean\"}]").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_spider"))
+        .arg("-o")
+        .arg(&output)
+        .arg("--sites-config")
+        .arg(&config)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("site registry"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repair_only_clears_done_months_that_lost_their_stories() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+    spider(&output, addr, &[]).await;
+
+    {
+        let db = open(&output);
+        let count = db.done.get(format!("{SITE}-2020-1")).unwrap().unwrap();
+        assert_eq!(count[..], 150u64.to_be_bytes());
+        // a month the API had no stories for, one that lost them and one from before
+        // markers held the count
+        db.done
+            .insert(format!("{SITE}-2019-1"), 0u64.to_be_bytes())
+            .unwrap();
+        db.done
+            .insert(format!("{SITE}-2019-2"), 5u64.to_be_bytes())
+            .unwrap();
+        db.done.insert(format!("{SITE}-2019-3"), []).unwrap();
+        // the index of a site no longer in the registry
+        let mut key = vec![250];
+        key.extend_from_slice(&0i64.to_be_bytes());
+        key.extend_from_slice(b"gone/story");
+        db.index.insert(key, []).unwrap();
+    }
+
+    spider(&output, addr, &["verify", "--repair"]).await;
+    let db = open(&output);
+    let done: Vec<String> = db
+        .done
+        .keys()
+        .map(|k| String::from_utf8(k.unwrap().to_vec()).unwrap())
+        .collect();
+    assert_eq!(
+        done,
+        [
+            format!("{SITE}-2019-1"),
+            format!("{SITE}-2019-3"),
+            format!("{SITE}-2020-1")
+        ]
+    );
+    assert_eq!(db.index.prefix([250]).count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_page_of_duplicates_does_not_end_the_crawl() {
+    let (_tmp, fixtures, output) = fixtures();
+    let pages = [
+        (
+            0,
+            json!({ "content_elements": (0..100).map(story).collect::<Vec<_>>(), "count": 250, "next": 100 }),
+        ),
+        // the result set shifted by a whole page
+        (
+            100,
+            json!({ "content_elements": (0..100).map(story).collect::<Vec<_>>(), "count": 250, "next": 200 }),
+        ),
+        (
+            200,
+            json!({ "content_elements": (100..150).map(story).collect::<Vec<_>>(), "count": 250 }),
+        ),
+    ];
+    for (offset, page) in pages {
+        save(
+            &fixtures,
+            &page_url(offset),
+            "application/json",
+            page.to_string().as_bytes(),
+        );
+    }
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert_eq!(db.rfa.len().unwrap(), 150);
+    assert!(db.rfa.contains_key("korean/news/story-149.html").unwrap());
+    assert!(db.done.contains_key(format!("{SITE}-2020-1")).unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_feed_is_fetched_once_per_crawl() {
+    let (_tmp, fixtures, output) = fixtures();
+    let feed = "https://example.org/feed";
+    let rss = "<rss><channel><item><title>Fish</title><link>https://example.org/news/fish</link>\
+        <pubDate>Tue, 14 Jan 2020 09:00:00 +0000</pubDate></item></channel></rss>";
+    save(&fixtures, feed, "application/rss+xml", rss.as_bytes());
+    let config = fixtures.join("sites.json");
+    let sites = json!([{
+        "id": "news",
+        "prefix": "news",
+        "code": 20,
+        "lang": "en",
+        "name": "News",
+        "logo": "logo-news.png",
+        "source": { "type": "rss", "feed": feed },
+    }]);
+    std::fs::write(&config, sites.to_string()).unwrap();
+    let addr = replay_server(&fixtures).await;
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spider"));
+    cmd.args(["-w", "news", "--from", "2019-12", "--to", "2020-02", "-o"])
+        .arg(&output)
+        .arg("--sites-config")
+        .arg(&config)
+        .args(["--replay", &addr.to_string()]);
+    let out = tokio::task::spawn_blocking(move || cmd.output().unwrap())
+        .await
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let db = open(&output);
+    assert!(db.rfa.contains_key("news/fish").unwrap());
+    let run = last_run(&db);
+    assert_eq!(run.windows.len(), 1);
+    assert_eq!(run.windows[0].begin, "2019-12-01");
+    assert_eq!(run.windows[0].end, "2020-02-29");
+    // the feed changes, its months are never done
+    assert!(db.done.is_empty().unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn old_failures_are_retried_on_the_host_of_their_site() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+    spider(&output, addr, &[]).await;
+
+    // failures recorded before they held the source of the original
+    {
+        let db = open(&output);
+        let old = json!({ "kind": "imgs" }).to_string();
+        db.failed.insert("/korean/lost.png", &old).unwrap();
+        db.failed.insert("/elsewhere/lost.png", &old).unwrap();
+        let hopeless = json!({ "kind": "imgs", "src": "https://www.rfa.org/korean/hopeless.png", "attempts": 5 });
+        db.failed
+            .insert("/korean/hopeless.png", hopeless.to_string())
+            .unwrap();
+    }
+    spider(&output, addr, &["--recrawl"]).await;
+    let db = open(&output);
+    let failure =
+        |url| -> Value { serde_json::from_slice(&db.failed.get(url).unwrap().unwrap()).unwrap() };
+    let retried = failure("/korean/lost.png");
+    assert_eq!(retried["src"], "https://www.rfa.org/korean/lost.png");
+    assert_eq!(retried["attempts"], 1);
+    // no site owns it, so its host is unknown
+    assert_eq!(failure("/elsewhere/lost.png"), json!({ "kind": "imgs" }));
+    // retried enough, it waits for a repair
+    assert_eq!(failure("/korean/hopeless.png")["attempts"], 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_stories_are_skipped() {
+    let (_tmp, fixtures, output) = fixtures();
+    let mut stories: Vec<Value> = (0..3).map(story).collect();
+    let mut without_url = story(3);
+    without_url["websites"] = json!({});
+    let mut without_date = story(4);
+    without_date.as_object_mut().unwrap().remove("display_date");
+    let mut bad_date = story(5);
+    bad_date["display_date"] = json!("January 2020");
+    stories.extend([without_url, without_date, bad_date]);
+    let page = json!({ "content_elements": stories, "count": 6 });
+    save(
+        &fixtures,
+        &page_url(0),
+        "application/json",
+        page.to_string().as_bytes(),
+    );
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert_eq!(db.rfa.len().unwrap(), 3);
+    assert_eq!(last_run(&db).windows[0].stored, 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_outage_does_not_mark_months_done() {
+    let (_tmp, fixtures, output) = fixtures();
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let error = json!({ "message": "Service Unavailable" }).to_string();
+    replay::save(&fixtures, &page_url(0), 503, &headers, error.as_bytes()).unwrap();
+    save_sections(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert!(db.done.is_empty().unwrap());
+    let window = &last_run(&db).windows[0];
+    assert!(!window.complete);
+    assert!(window.errors[0].contains("503"), "{:?}", window.errors);
+
+    // nor does an error answered with a success status
+    drop(db);
+    save(
+        &fixtures,
+        &page_url(0),
+        "application/json",
+        error.as_bytes(),
+    );
+    spider(&output, addr, &[]).await;
+    let db = open(&output);
+    assert!(db.done.is_empty().unwrap());
+    assert!(!last_run(&db).windows[0].complete);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn website_ids_are_normalized() {
+    let (_tmp, fixtures, output) = fixtures();
+    save_page1(&fixtures);
+    save_page2(&fixtures);
+    save_sections(&fixtures);
+    save_imgs(&fixtures);
+    let addr = replay_server(&fixtures).await;
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spider"));
+    cmd.args([
+        "-w",
+        "RFA-Korean, rfa-korean,",
+        "--from",
+        "2020-01",
+        "--to",
+        "2020-01",
+    ])
+    .arg("-o")
+    .arg(&output)
+    .args(["--replay", &addr.to_string()]);
+    let out = tokio::task::spawn_blocking(move || cmd.output().unwrap())
+        .await
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let db = open(&output);
+    assert_eq!(db.rfa.len().unwrap(), 150);
+    assert_eq!(last_run(&db).sites, [SITE]);
+    drop(db);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_spider"))
+        .args(["-w", "rfa-klingon", "-o"])
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unknown website rfa-klingon"));
+}
@@ -0,0 +1,27 @@
+//! Limits of the [`Throttle`] shared by concurrent tasks.
+
+use std::{sync::Arc, time::Duration};
+
+use futures::future;
+use rfa::throttle::Throttle;
+use tokio::time::timeout;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_requests_stay_within_the_daily_budget() {
+    let throttle = Arc::new(Throttle::new(None, Duration::ZERO, Some(3), None));
+    let tasks = (0..10).map(|_| {
+        let throttle = throttle.clone();
+        tokio::spawn(async move {
+            timeout(Duration::from_millis(200), throttle.request())
+                .await
+                .is_ok()
+        })
+    });
+    let sent = future::join_all(tasks)
+        .await
+        .into_iter()
+        .filter(|ok| *ok.as_ref().unwrap())
+        .count();
+    assert_eq!(sent, 3);
+    assert_eq!(throttle.used().1, 3);
+}